@@ -3,8 +3,11 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use directories::ProjectDirs;
 use anyhow::Result;
+use crate::model::EntryType;
 use std::fs;
 use tiny_skia::Color;
+use smithay_client_toolkit::seat::keyboard::Modifiers;
+use smithay_client_toolkit::shell::wlr_layer::Anchor;
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
@@ -17,6 +20,15 @@ pub struct Config {
     pub sources: SourceConfig,
     #[serde(default)]
     pub theme: ThemeConfig,
+    #[serde(default)]
+    pub power: PowerConfig,
+    #[serde(default)]
+    pub convert: ConvertConfig,
+    #[serde(default)]
+    pub scripts: ScriptsConfig,
+    /// Sources defined by an external command, `[[custom_source]]`
+    #[serde(default)]
+    pub custom_source: Vec<CustomSourceConfig>,
 }
 
 #[allow(dead_code)]
@@ -26,20 +38,158 @@ pub struct GeneralConfig {
     pub history_size: usize,
     #[serde(default)]
     pub terminal: Option<String>,
+    #[serde(default = "default_filter_debounce_ms")]
+    pub filter_debounce_ms: u64,
+    #[serde(default = "default_true")]
+    pub dedupe_bin_desktop: bool,
+    #[serde(default = "default_true")]
+    pub cache_desktop: bool,
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// Order of the list while the query is empty
+    #[serde(default)]
+    pub default_sort: DefaultSort,
+    /// Maximum number of decoded icons kept in memory
+    #[serde(default = "default_icon_cache_size")]
+    pub icon_cache_size: usize,
+    /// Where rendered icons are saved for later runs, default
+    /// `~/.cache/runner/icons`
+    #[serde(default)]
+    pub icon_disk_cache_dir: Option<String>,
+    /// Size limit of the on-disk icon cache in megabytes (0 disables it)
+    #[serde(default = "default_icon_disk_cache_mb")]
+    pub icon_disk_cache_mb: u64,
+    /// Exit when the keyboard focus moves elsewhere
+    #[serde(default = "default_true")]
+    pub close_on_focus_loss: bool,
+    /// Command prepended to every launch, e.g. `systemd-run --user --scope --`.
+    /// Split with shell quoting, like item commands
+    #[serde(default)]
+    pub launch_prefix: String,
+    /// Reopen with the query the launcher was last closed with
+    #[serde(default)]
+    pub restore_query: bool,
+    /// What Tab fills the query with
+    #[serde(default)]
+    pub tab_completion: TabCompletion,
+    /// How many rows get a number key shortcut (0 disables them)
+    #[serde(default = "default_quick_select_count")]
+    pub quick_select_count: usize,
+    /// Modifier that has to be held for the number key shortcuts
+    #[serde(default)]
+    pub quick_select_modifier: QuickSelectModifier,
+    /// Move the selection to the row under the mouse pointer, rather than
+    /// only highlighting it until clicked
+    #[serde(default)]
+    pub hover_selects: bool,
+    /// Focus an app's open window instead of starting another instance.
+    /// Needs the wlr foreign toplevel protocol; windows are matched by
+    /// StartupWMClass, desktop file id or executable name.
+    #[serde(default)]
+    pub raise_if_running: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuickSelectModifier {
+    /// Bare digits launch; they can't be typed into the query
+    #[default]
+    None,
+    Alt,
+    Ctrl,
+    Super,
+}
+
+impl QuickSelectModifier {
+    pub fn is_held(self, modifiers: &Modifiers) -> bool {
+        match self {
+            QuickSelectModifier::None => true,
+            QuickSelectModifier::Alt => modifiers.alt,
+            QuickSelectModifier::Ctrl => modifiers.ctrl,
+            QuickSelectModifier::Super => modifiers.logo,
+        }
+    }
+}
+
+/// Number keys 1-9 and then 0 for the tenth row
+pub const MAX_QUICK_SELECT: usize = 10;
+
+impl GeneralConfig {
+    /// `quick_select_count`, capped at the number of digit keys
+    pub fn quick_select_slots(&self) -> usize {
+        self.quick_select_count.min(MAX_QUICK_SELECT)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// nucleo fuzzy matching (fzf-like)
+    #[default]
+    Fuzzy,
+    /// Name must start with the query, dmenu-style
+    Prefix,
+    /// Name must contain the query
+    Substring,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultSort {
+    /// Launch count weighted by how recent the launches were
+    #[default]
+    Frecency,
+    /// By name, ignoring case
+    Alpha,
+    /// Last launched first
+    Recent,
+    /// Most launched first
+    Usage,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TabCompletion {
+    /// The selected entry's full name
+    #[default]
+    Full,
+    /// The longest prefix shared by every match, like shell completion
+    Prefix,
 }
 
 fn default_history_size() -> usize { 50 }
+fn default_filter_debounce_ms() -> u64 { 30 }
+fn default_icon_cache_size() -> usize { 256 }
+fn default_icon_disk_cache_mb() -> u64 { 20 }
+fn default_quick_select_count() -> usize { 9 }
 
 impl Default for GeneralConfig {
     fn default() -> Self {
         Self {
             history_size: default_history_size(),
             terminal: None,
+            filter_debounce_ms: default_filter_debounce_ms(),
+            dedupe_bin_desktop: true,
+            cache_desktop: true,
+            match_mode: MatchMode::default(),
+            default_sort: DefaultSort::default(),
+            icon_cache_size: default_icon_cache_size(),
+            icon_disk_cache_dir: None,
+            icon_disk_cache_mb: default_icon_disk_cache_mb(),
+            close_on_focus_loss: true,
+            launch_prefix: String::new(),
+            restore_query: false,
+            tab_completion: TabCompletion::default(),
+            quick_select_count: default_quick_select_count(),
+            quick_select_modifier: QuickSelectModifier::default(),
+            hover_selects: false,
+            raise_if_running: false,
         }
     }
 }
 
-#[allow(dead_code)]
+/// Global switches for sources. A source only runs if the group lists it
+/// and its switch here is on.
 #[derive(Deserialize, Debug, Clone)]
 pub struct SourceConfig {
     #[serde(default = "default_true")]
@@ -52,6 +202,19 @@ pub struct SourceConfig {
 
 fn default_true() -> bool { true }
 
+impl SourceConfig {
+    /// Whether the source called `name` (as in a group's `sources`) is
+    /// switched on. Sources without a switch always are.
+    pub fn enabled(&self, name: &str) -> bool {
+        match name {
+            "bin" => self.scan_path,
+            "desktop" => self.scan_desktop,
+            "scripts" => self.scan_scripts,
+            _ => true,
+        }
+    }
+}
+
 impl Default for SourceConfig {
     fn default() -> Self {
         Self {
@@ -62,6 +225,90 @@ impl Default for SourceConfig {
     }
 }
 
+/// Commands behind the `power` source's entries. An empty string hides the
+/// entry.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PowerConfig {
+    #[serde(default = "default_power_lock")]
+    pub lock: String,
+    #[serde(default = "default_power_logout")]
+    pub logout: String,
+    #[serde(default = "default_power_suspend")]
+    pub suspend: String,
+    #[serde(default = "default_power_reboot")]
+    pub reboot: String,
+    #[serde(default = "default_power_poweroff")]
+    pub poweroff: String,
+}
+
+fn default_power_lock() -> String { "loginctl lock-session".to_string() }
+fn default_power_logout() -> String { "loginctl terminate-session $XDG_SESSION_ID".to_string() }
+fn default_power_suspend() -> String { "systemctl suspend".to_string() }
+fn default_power_reboot() -> String { "systemctl reboot".to_string() }
+fn default_power_poweroff() -> String { "systemctl poweroff".to_string() }
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            lock: default_power_lock(),
+            logout: default_power_logout(),
+            suspend: default_power_suspend(),
+            reboot: default_power_reboot(),
+            poweroff: default_power_poweroff(),
+        }
+    }
+}
+
+/// A source that runs `command` and reads one entry per output line
+#[derive(Deserialize, Debug, Clone)]
+pub struct CustomSourceConfig {
+    /// What groups list it as in `sources`
+    pub name: String,
+    /// Run with `sh -c`
+    pub command: String,
+    /// Tab-separated column names, see `sources::custom::COLUMNS`
+    #[serde(default = "default_custom_format")]
+    pub format: String,
+    /// Picking an entry runs `command` again for the next menu level
+    /// instead of launching it, see `sources::custom::CustomSource`
+    #[serde(default)]
+    pub script: bool,
+}
+
+fn default_custom_format() -> String { "name\tcommand\ticon".to_string() }
+
+/// Unit conversions typed into the query, e.g. "10 km to mi"
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConvertConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Exchange rates, as units of each currency per one of a base currency
+    /// of your choice. Never fetched; currencies are off while this is empty.
+    #[serde(default)]
+    pub rates: HashMap<String, f64>,
+}
+
+impl Default for ConvertConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rates: HashMap::new(),
+        }
+    }
+}
+
+/// Where the `scripts` source looks, besides `~/.config/runner/scripts`
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ScriptsConfig {
+    /// More script directories; `~` and `$VAR` are expanded
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    /// Also list scripts without the executable bit that have a known
+    /// extension, run through their interpreter
+    #[serde(default)]
+    pub include_non_executable: bool,
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct LaunchGroup {
@@ -70,8 +317,32 @@ pub struct LaunchGroup {
     pub env: Option<HashMap<String, String>>,
     pub blacklist: Option<Vec<String>>,
     pub whitelist: Option<Vec<String>>,
+    /// What the whitelist and blacklist patterns are matched against
+    #[serde(default)]
+    pub match_on: MatchOn,
+    /// Only show entries of these types, e.g. `["desktop"]`
+    pub include_types: Option<Vec<String>>,
+    /// Hide entries of these types, e.g. `["binary"]`
+    #[serde(default)]
+    pub exclude_types: Vec<String>,
+    /// Only show desktop apps in one of these categories, e.g. `["Game"]`
+    pub categories: Option<Vec<String>>,
     #[serde(default)]
     pub items: Vec<StaticEntry>,
+    pub placeholder: Option<String>,
+    pub prompt: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchOn {
+    /// The displayed name
+    Name,
+    /// The entry id, e.g. "firefox.desktop" or a binary's path
+    Id,
+    /// Either one
+    #[default]
+    Both,
 }
 
 #[allow(dead_code)]
@@ -84,13 +355,39 @@ pub struct StaticEntry {
     pub terminal: bool,
 }
 
+/// How the result list is split into sections
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    /// One flat list
+    #[default]
+    None,
+    /// By entry type: applications, commands, ...
+    Type,
+    /// By the source that found the entry
+    Source,
+}
+
+/// What to do with entry names too long for their row
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Ellipsize {
+    /// Cut off with "…"
+    #[default]
+    End,
+    /// Up to two lines, in taller rows
+    Wrap,
+    /// Run on past the row
+    None,
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
 pub struct ThemeConfig {
     #[serde(default = "default_width")]
-    pub width: u32,
+    pub width: Size,
     #[serde(default = "default_height")]
-    pub height: u32,
+    pub height: Size,
     #[serde(default = "default_padding")]
     pub padding: f32,
     #[serde(default = "default_spacing")]
@@ -109,10 +406,86 @@ pub struct ThemeConfig {
     pub selection_text: String,
     #[serde(default = "default_number_color")]
     pub number_color: String,
+    /// Background of the row under the mouse pointer
+    #[serde(default = "default_hover_background")]
+    pub hover_background: String,
+    /// Background of rows marked with Tab in `--multi` mode
+    #[serde(default = "default_marked_background")]
+    pub marked_background: String,
+    #[serde(default = "default_scrollbar_track")]
+    pub scrollbar_track: String,
+    #[serde(default = "default_scrollbar_thumb")]
+    pub scrollbar_thumb: String,
+    #[serde(default = "default_placeholder")]
+    pub placeholder: String,
+    #[serde(default = "default_prompt")]
+    pub prompt: String,
+    /// Color of the prompt in front of the query; `text` if unset
+    #[serde(default)]
+    pub prompt_color: Option<String>,
+    /// Color of the typed query; `text` if unset
+    #[serde(default)]
+    pub query_color: Option<String>,
+    /// Color of a text cursor after the query; no cursor if unset
+    #[serde(default)]
+    pub cursor_color: Option<String>,
+    /// How long the cursor stays on and off; 0 keeps it on
+    #[serde(default = "default_cursor_blink_ms")]
+    pub cursor_blink_ms: u64,
+    #[serde(default)]
+    pub show_count: bool,
+    #[serde(default = "default_count_color")]
+    pub count_color: String,
+    /// A strip below the list with the selected entry's type and command
+    #[serde(default)]
+    pub show_detail: bool,
+    /// Split the results into sections with a header each
+    #[serde(default)]
+    pub grouped: Grouping,
+    /// Badge color per entry type ("desktop", "binary", ...); types not
+    /// listed get no badge
+    #[serde(default)]
+    pub type_colors: HashMap<String, String>,
+    /// Shown in place of the list when nothing matches; empty shows nothing
+    #[serde(default = "default_no_results_text")]
+    pub no_results_text: String,
+    #[serde(default = "default_no_results_color")]
+    pub no_results_color: String,
+    /// Icon name or path used when an entry's icon can't be found
+    #[serde(default)]
+    pub fallback_icon: Option<String>,
+    /// Icon theme to use instead of the one GTK is configured with
+    #[serde(default)]
+    pub icon_theme: Option<String>,
+    /// Square drawn in an icon's place while it loads
+    #[serde(default = "default_icon_placeholder_color")]
+    pub icon_placeholder_color: String,
+    /// Screen edge(s) to dock to: "center", "top", "bottom-left", ...
+    #[serde(default = "default_anchor")]
+    pub anchor: String,
+    #[serde(default)]
+    pub margin_top: i32,
+    #[serde(default)]
+    pub margin_right: i32,
+    #[serde(default)]
+    pub margin_bottom: i32,
+    #[serde(default)]
+    pub margin_left: i32,
+    /// Shrink the window to fit the results, up to `height`
+    #[serde(default)]
+    pub dynamic_height: bool,
+    /// Output (monitor) to show on, by name ("DP-1") or description
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Fade in on open and out on close over this many milliseconds; 0 is off
+    #[serde(default)]
+    pub fade_ms: u64,
+    #[serde(default)]
+    pub ellipsize: Ellipsize,
 }
 
-fn default_width() -> u32 { 600 }
-fn default_height() -> u32 { 400 }
+fn default_width() -> Size { Size::Pixels(600) }
+fn default_height() -> Size { Size::Pixels(400) }
 fn default_padding() -> f32 { 20.0 }
 fn default_spacing() -> f32 { 10.0 }
 fn default_border_radius() -> f32 { 12.0 }
@@ -122,6 +495,18 @@ fn default_text() -> String { "c8c8c8ff".to_string() }
 fn default_selection_background() -> String { "3c3c50ff".to_string() }
 fn default_selection_text() -> String { "ffffffff".to_string() }
 fn default_number_color() -> String { "646464ff".to_string() }
+fn default_hover_background() -> String { "2c2c38ff".to_string() }
+fn default_marked_background() -> String { "2e3a2eff".to_string() }
+fn default_scrollbar_track() -> String { "2a2a2aff".to_string() }
+fn default_scrollbar_thumb() -> String { "646464ff".to_string() }
+fn default_placeholder() -> String { "Search apps...".to_string() }
+fn default_prompt() -> String { ">".to_string() }
+fn default_cursor_blink_ms() -> u64 { 500 }
+fn default_count_color() -> String { "646464ff".to_string() }
+fn default_no_results_text() -> String { "No results found".to_string() }
+fn default_no_results_color() -> String { "966464ff".to_string() }
+fn default_icon_placeholder_color() -> String { "ffffff14".to_string() }
+fn default_anchor() -> String { "center".to_string() }
 
 impl Default for ThemeConfig {
     fn default() -> Self {
@@ -137,25 +522,257 @@ impl Default for ThemeConfig {
             selection_background: default_selection_background(),
             selection_text: default_selection_text(),
             number_color: default_number_color(),
+            hover_background: default_hover_background(),
+            marked_background: default_marked_background(),
+            scrollbar_track: default_scrollbar_track(),
+            scrollbar_thumb: default_scrollbar_thumb(),
+            placeholder: default_placeholder(),
+            prompt: default_prompt(),
+            prompt_color: None,
+            query_color: None,
+            cursor_color: None,
+            cursor_blink_ms: default_cursor_blink_ms(),
+            show_count: false,
+            count_color: default_count_color(),
+            show_detail: false,
+            grouped: Grouping::None,
+            type_colors: HashMap::new(),
+            no_results_text: default_no_results_text(),
+            no_results_color: default_no_results_color(),
+            fallback_icon: None,
+            icon_theme: None,
+            icon_placeholder_color: default_icon_placeholder_color(),
+            anchor: default_anchor(),
+            margin_top: 0,
+            margin_right: 0,
+            margin_bottom: 0,
+            margin_left: 0,
+            dynamic_height: false,
+            output: None,
+            fade_ms: 0,
+            ellipsize: Ellipsize::End,
         }
     }
 }
 
 impl ThemeConfig {
+    /// Parses a color, falling back to black for invalid values. Invalid
+    /// colors are reported once when the config is loaded.
     pub fn parse_color(hex: &str) -> Color {
-        let hex = hex.trim_start_matches('#');
-        if hex.len() != 8 {
-            return Color::BLACK;
+        Self::try_parse_color(hex).unwrap_or(Color::BLACK)
+    }
+
+    /// Accepts CSS color names (`"black"`, `"rebeccapurple"`) and `rgb`,
+    /// `rgba`, `rrggbb` and `rrggbbaa` hex, with or without a leading `#`.
+    /// Alpha defaults to fully opaque when omitted.
+    pub fn try_parse_color(value: &str) -> Option<Color> {
+        let value = value.trim();
+        let hex = match named_color(value) {
+            Some(hex) => hex,
+            None => value.trim_start_matches('#'),
+        };
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let channel = |i: usize, width: usize| -> Option<u8> {
+            let v = u8::from_str_radix(hex.get(i * width..(i + 1) * width)?, 16).ok()?;
+            // Shorthand digits are doubled: "f" -> "ff"
+            Some(if width == 1 { v * 17 } else { v })
+        };
+
+        let (width, has_alpha) = match hex.len() {
+            3 => (1, false),
+            4 => (1, true),
+            6 => (2, false),
+            8 => (2, true),
+            _ => return None,
+        };
+        let a = if has_alpha { channel(3, width)? } else { 255 };
+
+        Some(Color::from_rgba8(channel(0, width)?, channel(1, width)?, channel(2, width)?, a))
+    }
+
+    /// Layer-surface anchor for `anchor`, centered if it isn't recognized.
+    pub fn anchor(&self) -> Anchor {
+        Self::try_parse_anchor(&self.anchor).unwrap_or(Anchor::empty())
+    }
+
+    pub fn try_parse_anchor(value: &str) -> Option<Anchor> {
+        let anchor = match value.trim().to_ascii_lowercase().as_str() {
+            "center" => Anchor::empty(),
+            "top" => Anchor::TOP,
+            "bottom" => Anchor::BOTTOM,
+            "left" => Anchor::LEFT,
+            "right" => Anchor::RIGHT,
+            "top-left" => Anchor::TOP | Anchor::LEFT,
+            "top-right" => Anchor::TOP | Anchor::RIGHT,
+            "bottom-left" => Anchor::BOTTOM | Anchor::LEFT,
+            "bottom-right" => Anchor::BOTTOM | Anchor::RIGHT,
+            _ => return None,
+        };
+        Some(anchor)
+    }
+
+    /// Whether every pixel of the window is fully opaque: square corners and
+    /// a background and border with an alpha of exactly ff. Anything less,
+    /// even fe, lets what's behind show through.
+    pub fn is_opaque(&self) -> bool {
+        self.border_radius <= 0.0
+            && Self::parse_color(&self.background).is_opaque()
+            && Self::parse_color(&self.border_color).is_opaque()
+    }
+
+    /// The `type_colors` badge color for `entry_type`, if it has one.
+    pub fn type_color(&self, entry_type: &EntryType) -> Option<Color> {
+        self.type_colors.iter()
+            .find(|(name, _)| EntryType::from_name(name).as_ref() == Some(entry_type))
+            .map(|(_, color)| Self::parse_color(color))
+    }
+
+    /// All color settings as `(key, value)` pairs, for validation.
+    pub fn colors(&self) -> Vec<(&'static str, &str)> {
+        let optional = [
+            ("prompt_color", &self.prompt_color),
+            ("query_color", &self.query_color),
+            ("cursor_color", &self.cursor_color),
+        ];
+        let optional = optional.into_iter().filter_map(|(key, value)| Some((key, value.as_deref()?)));
+        let mut colors: Vec<(&'static str, &str)> = vec![
+            ("background", &self.background),
+            ("border_color", &self.border_color),
+            ("text", &self.text),
+            ("selection_background", &self.selection_background),
+            ("selection_text", &self.selection_text),
+            ("number_color", &self.number_color),
+            ("hover_background", &self.hover_background),
+            ("marked_background", &self.marked_background),
+            ("scrollbar_track", &self.scrollbar_track),
+            ("scrollbar_thumb", &self.scrollbar_thumb),
+            ("count_color", &self.count_color),
+            ("no_results_color", &self.no_results_color),
+            ("icon_placeholder_color", &self.icon_placeholder_color),
+        ];
+        colors.extend(optional);
+        colors
+    }
+}
+
+/// A window dimension: `600` pixels, or `"60%"` of the output's logical size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Size {
+    /// Concrete pixels for an output `output_len` pixels long. Percentages
+    /// fall back to `fallback` when the output size is unknown.
+    pub fn resolve(self, output_len: Option<i32>, fallback: u32) -> u32 {
+        match (self, output_len) {
+            (Size::Pixels(px), _) => px,
+            (Size::Percent(pct), Some(len)) => (len as f32 * pct / 100.0).round().max(1.0) as u32,
+            (Size::Percent(_), None) => fallback,
+        }
+    }
+
+    fn is_valid(self) -> bool {
+        match self {
+            Size::Pixels(px) => px > 0,
+            Size::Percent(pct) => pct > 0.0 && pct <= 100.0,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Pixels(u32),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Pixels(px) => Ok(Size::Pixels(px)),
+            Raw::Text(text) => {
+                let text = text.trim();
+                let parsed = match text.strip_suffix('%') {
+                    Some(pct) => pct.trim().parse().ok().map(Size::Percent),
+                    None => text.parse().ok().map(Size::Pixels),
+                };
+                parsed.ok_or_else(|| serde::de::Error::custom(format!("invalid size {:?}, expected pixels or a percentage like \"60%\"", text)))
+            }
         }
-        let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0);
-        let g = u8::from_str_radix(&hex[2..4], 16).unwrap_or(0);
-        let b = u8::from_str_radix(&hex[4..6], 16).unwrap_or(0);
-        let a = u8::from_str_radix(&hex[6..8], 16).unwrap_or(255);
-        
-        Color::from_rgba8(r, g, b, a)
     }
 }
 
+/// Common CSS named colors, resolved to `rrggbb`/`rrggbbaa` hex.
+const NAMED_COLORS: &[(&str, &str)] = &[
+    ("transparent", "00000000"),
+    ("black", "000000"),
+    ("white", "ffffff"),
+    ("silver", "c0c0c0"),
+    ("gray", "808080"),
+    ("grey", "808080"),
+    ("darkgray", "a9a9a9"),
+    ("darkgrey", "a9a9a9"),
+    ("lightgray", "d3d3d3"),
+    ("lightgrey", "d3d3d3"),
+    ("dimgray", "696969"),
+    ("dimgrey", "696969"),
+    ("gainsboro", "dcdcdc"),
+    ("whitesmoke", "f5f5f5"),
+    ("red", "ff0000"),
+    ("darkred", "8b0000"),
+    ("maroon", "800000"),
+    ("crimson", "dc143c"),
+    ("tomato", "ff6347"),
+    ("coral", "ff7f50"),
+    ("salmon", "fa8072"),
+    ("orange", "ffa500"),
+    ("darkorange", "ff8c00"),
+    ("gold", "ffd700"),
+    ("yellow", "ffff00"),
+    ("khaki", "f0e68c"),
+    ("olive", "808000"),
+    ("lime", "00ff00"),
+    ("green", "008000"),
+    ("darkgreen", "006400"),
+    ("seagreen", "2e8b57"),
+    ("teal", "008080"),
+    ("cyan", "00ffff"),
+    ("aqua", "00ffff"),
+    ("turquoise", "40e0d0"),
+    ("skyblue", "87ceeb"),
+    ("steelblue", "4682b4"),
+    ("royalblue", "4169e1"),
+    ("blue", "0000ff"),
+    ("navy", "000080"),
+    ("midnightblue", "191970"),
+    ("indigo", "4b0082"),
+    ("purple", "800080"),
+    ("rebeccapurple", "663399"),
+    ("violet", "ee82ee"),
+    ("magenta", "ff00ff"),
+    ("fuchsia", "ff00ff"),
+    ("orchid", "da70d6"),
+    ("pink", "ffc0cb"),
+    ("hotpink", "ff69b4"),
+    ("brown", "a52a2a"),
+    ("chocolate", "d2691e"),
+    ("tan", "d2b48c"),
+    ("beige", "f5f5dc"),
+    ("ivory", "fffff0"),
+    ("linen", "faf0e6"),
+];
+
+fn named_color(name: &str) -> Option<&'static str> {
+    NAMED_COLORS.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, hex)| *hex)
+}
+
 impl Default for Config {
     fn default() -> Self {
         let mut groups = HashMap::new();
@@ -164,7 +781,13 @@ impl Default for Config {
             env: None,
             blacklist: None,
             whitelist: None,
+            match_on: MatchOn::default(),
+            include_types: None,
+            exclude_types: vec![],
+            categories: None,
             items: vec![],
+            placeholder: None,
+            prompt: None,
         });
 
         Self {
@@ -172,23 +795,208 @@ impl Default for Config {
             groups,
             sources: SourceConfig::default(),
             theme: ThemeConfig::default(),
+            power: PowerConfig::default(),
+            convert: ConvertConfig::default(),
+            scripts: ScriptsConfig::default(),
+            custom_source: Vec::new(),
         }
     }
 }
 
-pub fn load_config() -> Result<Config> {
+pub fn config_path() -> PathBuf {
     let proj_dirs = ProjectDirs::from("org", "runner", "runner");
-    let config_path = if let Some(dirs) = &proj_dirs {
+    if let Some(dirs) = &proj_dirs {
         dirs.config_dir().join("config.toml")
     } else {
         PathBuf::from("config.toml")
+    }
+}
+
+/// Load `path`, or the default config location when `None`. A missing file
+/// at the default location means defaults; an explicit path must exist.
+pub fn load_config(path: Option<PathBuf>) -> Result<Config> {
+    let config_path = match path {
+        Some(path) => {
+            if !path.exists() {
+                anyhow::bail!("{}: no such file", path.display());
+            }
+            path
+        }
+        None => {
+            let path = config_path();
+            if !path.exists() {
+                return Ok(Config::default());
+            }
+            path
+        }
     };
 
-    if !config_path.exists() {
-        return Ok(Config::default());
+    let content = fs::read_to_string(&config_path)?;
+    let config: Config = toml::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("{}: {}", config_path.display(), e))?;
+    Ok(config)
+}
+
+/// Source names a group may list in `sources`
+pub const KNOWN_SOURCES: &[&str] = &["desktop", "bin", "scripts", "history", "recent", "power"];
+
+pub struct ConfigIssue {
+    /// Fatal issues leave nothing sensible to fall back on
+    pub fatal: bool,
+    pub message: String,
+}
+
+impl Config {
+    /// The `[[custom_source]]` named `name` if it is a drill-down script.
+    pub fn script_source(&self, name: &str) -> Option<&CustomSourceConfig> {
+        self.custom_source.iter().find(|c| c.script && c.name == name)
     }
 
-    let content = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&content)?;
-    Ok(config)
+    /// Collect every problem in the config instead of stopping at the first,
+    /// so a single run shows everything that needs fixing.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut warn = |message: String| issues.push(ConfigIssue { fatal: false, message });
+
+        for (key, value) in self.theme.colors() {
+            if ThemeConfig::try_parse_color(value).is_none() {
+                warn(format!("theme.{} = {:?} is not a valid color, using black", key, value));
+            }
+        }
+
+        let mut type_colors: Vec<_> = self.theme.type_colors.iter().collect();
+        type_colors.sort();
+        for (type_name, value) in type_colors {
+            if EntryType::from_name(type_name).is_none() {
+                warn(format!(
+                    "theme.type_colors: unknown entry type {:?}, ignoring it (known: {})",
+                    type_name, EntryType::NAMES.join(", ")
+                ));
+            } else if ThemeConfig::try_parse_color(value).is_none() {
+                warn(format!("theme.type_colors.{} = {:?} is not a valid color, using black", type_name, value));
+            }
+        }
+
+        let mut group_names: Vec<&String> = self.groups.keys().collect();
+        group_names.sort();
+        for name in &group_names {
+            for source in &self.groups[*name].sources {
+                if !KNOWN_SOURCES.contains(&source.as_str())
+                    && !self.custom_source.iter().any(|c| &c.name == source) {
+                    warn(format!(
+                        "groups.{}.sources: unknown source {:?}, ignoring it (known: {})",
+                        name, source, KNOWN_SOURCES.join(", ")
+                    ));
+                }
+            }
+        }
+
+        for name in &group_names {
+            let group = &self.groups[*name];
+            let lists = [("whitelist", &group.whitelist), ("blacklist", &group.blacklist)];
+            for (list, patterns) in lists {
+                for pattern in patterns.iter().flatten() {
+                    if let Err(e) = regex::Regex::new(pattern) {
+                        warn(format!("groups.{}.{}: invalid pattern {:?}, ignoring it: {}", name, list, pattern, e));
+                    }
+                }
+            }
+        }
+
+        for name in &group_names {
+            let group = &self.groups[*name];
+            for type_name in group.include_types.iter().flatten().chain(&group.exclude_types) {
+                if EntryType::from_name(type_name).is_none() {
+                    warn(format!(
+                        "groups.{}: unknown entry type {:?}, ignoring it (known: {})",
+                        name, type_name, EntryType::NAMES.join(", ")
+                    ));
+                }
+            }
+        }
+
+        for custom in &self.custom_source {
+            if KNOWN_SOURCES.contains(&custom.name.as_str()) {
+                warn(format!("custom_source {:?} has the name of a built-in source and will never run", custom.name));
+            }
+            for column in custom.format.split('\t').map(str::trim) {
+                if !crate::sources::custom::COLUMNS.contains(&column) {
+                    warn(format!(
+                        "custom_source {:?}: unknown column {:?} in format, ignoring it (known: {})",
+                        custom.name, column, crate::sources::custom::COLUMNS.join(", ")
+                    ));
+                }
+            }
+        }
+
+        if self.general.quick_select_count > MAX_QUICK_SELECT {
+            warn(format!(
+                "general.quick_select_count = {} is more than there are digit keys, using {}",
+                self.general.quick_select_count, MAX_QUICK_SELECT
+            ));
+        }
+
+        if ThemeConfig::try_parse_anchor(&self.theme.anchor).is_none() {
+            warn(format!("theme.anchor = {:?} is not a valid anchor, using \"center\"", self.theme.anchor));
+        }
+
+        if !self.theme.width.is_valid() {
+            issues.push(ConfigIssue { fatal: true, message: "theme.width must be greater than 0 (and at most 100%)".to_string() });
+        }
+        if !self.theme.height.is_valid() {
+            issues.push(ConfigIssue { fatal: true, message: "theme.height must be greater than 0 (and at most 100%)".to_string() });
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(value: &str) -> Option<[u8; 4]> {
+        let c = ThemeConfig::try_parse_color(value)?.to_color_u8();
+        Some([c.red(), c.green(), c.blue(), c.alpha()])
+    }
+
+    #[test]
+    fn hex_colors_in_every_length() {
+        assert_eq!(rgba("#f80"), Some([0xff, 0x88, 0x00, 0xff]));
+        assert_eq!(rgba("f808"), Some([0xff, 0x88, 0x00, 0x88]));
+        assert_eq!(rgba("#1e1e2e"), Some([0x1e, 0x1e, 0x2e, 0xff]));
+        assert_eq!(rgba("1e1e2ef0"), Some([0x1e, 0x1e, 0x2e, 0xf0]));
+        assert_eq!(rgba("  #FFFFFF  "), Some([0xff, 0xff, 0xff, 0xff]));
+    }
+
+    #[test]
+    fn css_color_names() {
+        assert_eq!(rgba("RebeccaPurple"), Some([0x66, 0x33, 0x99, 0xff]));
+        assert_eq!(rgba("transparent"), Some([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn invalid_colors() {
+        for value in ["", "#", "#12", "#12345", "#1234567", "#gggggg", "notacolor", "#ff000000ff"] {
+            assert_eq!(rgba(value), None, "{:?}", value);
+        }
+        assert_eq!(ThemeConfig::parse_color("nope"), Color::BLACK);
+    }
+
+    #[test]
+    fn opaque_needs_square_corners_and_solid_colors() {
+        let theme = ThemeConfig {
+            border_radius: 0.0,
+            background: "#1e1e2e".to_string(),
+            border_color: "3c3c50ff".to_string(),
+            ..ThemeConfig::default()
+        };
+        assert!(theme.is_opaque());
+
+        let rounded = ThemeConfig { border_radius: 0.5, ..theme.clone() };
+        assert!(!rounded.is_opaque());
+        let see_through = ThemeConfig { background: "1e1e2efe".to_string(), ..theme.clone() };
+        assert!(!see_through.is_opaque());
+        let faded_border = ThemeConfig { border_color: "transparent".to_string(), ..theme };
+        assert!(!faded_border.is_opaque());
+    }
 }
@@ -0,0 +1,199 @@
+use crate::config::ConvertConfig;
+use crate::model::{Entry, EntryType};
+
+/// Id prefix of conversion results. They change with every query, so
+/// launching one isn't recorded in history.
+pub const ID_PREFIX: &str = "convert:";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dimension {
+    Length,
+    Mass,
+    Temperature,
+    Currency,
+}
+
+/// Names a unit can be typed as, its dimension and its size in the
+/// dimension's base unit (metres, grams). Temperatures are converted through
+/// `to_kelvin`/`from_kelvin` instead.
+const UNITS: &[(&[&str], Dimension, f64)] = &[
+    (&["mm", "millimeter", "millimeters", "millimetre", "millimetres"], Dimension::Length, 0.001),
+    (&["cm", "centimeter", "centimeters", "centimetre", "centimetres"], Dimension::Length, 0.01),
+    (&["m", "meter", "meters", "metre", "metres"], Dimension::Length, 1.0),
+    (&["km", "kilometer", "kilometers", "kilometre", "kilometres"], Dimension::Length, 1000.0),
+    (&["in", "inch", "inches"], Dimension::Length, 0.0254),
+    (&["ft", "foot", "feet"], Dimension::Length, 0.3048),
+    (&["yd", "yard", "yards"], Dimension::Length, 0.9144),
+    (&["mi", "mile", "miles"], Dimension::Length, 1609.344),
+    (&["nmi"], Dimension::Length, 1852.0),
+    (&["mg", "milligram", "milligrams"], Dimension::Mass, 0.001),
+    (&["g", "gram", "grams"], Dimension::Mass, 1.0),
+    (&["kg", "kilogram", "kilograms"], Dimension::Mass, 1000.0),
+    (&["t", "tonne", "tonnes"], Dimension::Mass, 1_000_000.0),
+    (&["oz", "ounce", "ounces"], Dimension::Mass, 28.349523125),
+    (&["lb", "lbs", "pound", "pounds"], Dimension::Mass, 453.59237),
+    (&["st", "stone", "stones"], Dimension::Mass, 6350.29318),
+    (&["c", "°c", "celsius"], Dimension::Temperature, 0.0),
+    (&["f", "°f", "fahrenheit"], Dimension::Temperature, 0.0),
+    (&["k", "kelvin"], Dimension::Temperature, 0.0),
+];
+
+struct Unit {
+    name: String,
+    dimension: Dimension,
+    factor: f64,
+}
+
+/// A result entry for queries like "10 km to mi" or "100 usd in eur", or
+/// `None` if the query isn't a conversion. Selecting it copies the result to
+/// the clipboard with `wl-copy`.
+pub fn convert(query: &str, config: &ConvertConfig) -> Option<Entry> {
+    if !config.enabled {
+        return None;
+    }
+
+    let tokens: Vec<&str> = query.split_whitespace().collect();
+    // "10 km to mi", or with the number and unit written together, "10km to mi"
+    let (amount, from, to) = match tokens.as_slice() {
+        [amount, from, "to" | "in", to] => (amount.parse::<f64>().ok()?, *from, *to),
+        [amount_from, "to" | "in", to] => {
+            let split = amount_from.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))?;
+            let (amount, from) = amount_from.split_at(split);
+            (amount.parse::<f64>().ok()?, from, *to)
+        }
+        _ => return None,
+    };
+
+    let from = lookup(from, config)?;
+    let to = lookup(to, config)?;
+    if from.dimension != to.dimension {
+        return None;
+    }
+
+    let result = match from.dimension {
+        Dimension::Temperature => from_kelvin(to_kelvin(amount, &from.name), &to.name),
+        _ => amount * from.factor / to.factor,
+    };
+    if !result.is_finite() {
+        return None;
+    }
+
+    let result = format!("{} {}", format_number(result), to.name);
+    let mut entry = Entry::new(
+        format!("{}{}", ID_PREFIX, query.trim()),
+        format!("{} {} = {}", format_number(amount), from.name, result),
+        format!("wl-copy -- {}", result),
+        EntryType::Custom,
+        false,
+    );
+    entry.icon = Some("accessories-calculator".to_string());
+    Some(entry)
+}
+
+fn lookup(name: &str, config: &ConvertConfig) -> Option<Unit> {
+    let lower = name.to_lowercase();
+    if let Some((names, dimension, factor)) = UNITS.iter().find(|(names, _, _)| names.contains(&lower.as_str())) {
+        // Temperatures are told apart by their short name
+        let name = if *dimension == Dimension::Temperature { names[0].to_uppercase() } else { lower };
+        return Some(Unit { name, dimension: *dimension, factor: *factor });
+    }
+
+    // Rates are units of currency per one of the base currency, so
+    // converting divides by them
+    let (code, rate) = config.rates.iter().find(|(code, _)| code.eq_ignore_ascii_case(name))?;
+    (*rate > 0.0).then(|| Unit { name: code.to_uppercase(), dimension: Dimension::Currency, factor: 1.0 / rate })
+}
+
+fn to_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "C" => value + 273.15,
+        "F" => (value - 32.0) * 5.0 / 9.0 + 273.15,
+        _ => value,
+    }
+}
+
+fn from_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "C" => value - 273.15,
+        "F" => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        _ => value,
+    }
+}
+
+/// Up to four decimals, without trailing zeros.
+fn format_number(value: f64) -> String {
+    let formatted = format!("{:.4}", value);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" { "0".to_string() } else { trimmed.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converted(query: &str, config: &ConvertConfig) -> Option<String> {
+        convert(query, config).map(|entry| entry.name)
+    }
+
+    fn with_rates(rates: &[(&str, f64)]) -> ConvertConfig {
+        ConvertConfig {
+            rates: rates.iter().map(|(code, rate)| (code.to_string(), *rate)).collect(),
+            ..ConvertConfig::default()
+        }
+    }
+
+    #[test]
+    fn lengths_with_and_without_a_space() {
+        let config = ConvertConfig::default();
+        assert_eq!(converted("10 km to mi", &config).as_deref(), Some("10 km = 6.2137 mi"));
+        assert_eq!(converted("10km to mi", &config).as_deref(), Some("10 km = 6.2137 mi"));
+        assert_eq!(converted("2.5 Feet in cm", &config).as_deref(), Some("2.5 feet = 76.2 cm"));
+
+        let entry = convert(" 10 km to mi ", &config).unwrap();
+        assert_eq!(entry.id, "convert:10 km to mi");
+        assert_eq!(entry.command, "wl-copy -- 6.2137 mi");
+    }
+
+    #[test]
+    fn temperatures_go_through_kelvin() {
+        let config = ConvertConfig::default();
+        assert_eq!(converted("100 c to f", &config).as_deref(), Some("100 C = 212 F"));
+        assert_eq!(converted("32 °F to celsius", &config).as_deref(), Some("32 F = 0 C"));
+        assert_eq!(converted("0 k in c", &config).as_deref(), Some("0 K = -273.15 C"));
+        assert_eq!(converted("-40 f to c", &config).as_deref(), Some("-40 F = -40 C"));
+    }
+
+    #[test]
+    fn currencies_use_the_configured_rates() {
+        let config = with_rates(&[("usd", 1.0), ("EUR", 0.9), ("bad", 0.0)]);
+        assert_eq!(converted("100 usd in eur", &config).as_deref(), Some("100 USD = 90 EUR"));
+        assert_eq!(converted("9 EUR to USD", &config).as_deref(), Some("9 EUR = 10 USD"));
+        assert_eq!(converted("1 usd to bad", &config), None);
+        assert_eq!(converted("100 usd in eur", &ConvertConfig::default()), None);
+    }
+
+    #[test]
+    fn anything_else_is_not_a_conversion() {
+        let config = with_rates(&[("usd", 1.0)]);
+        for query in [
+            "10 km to parsecs", "10 kg to km", "10 c to m", "10 usd to kg",
+            "ten km to mi", "km to mi", "10 km mi", "10 km to mi please", "firefox",
+        ] {
+            assert_eq!(converted(query, &config), None, "{:?}", query);
+        }
+
+        let disabled = ConvertConfig { enabled: false, ..ConvertConfig::default() };
+        assert_eq!(converted("10 km to mi", &disabled), None);
+    }
+
+    #[test]
+    fn format_number_trims_trailing_zeros() {
+        assert_eq!(format_number(2.0), "2");
+        assert_eq!(format_number(1.5), "1.5");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(1.0 / 3.0), "0.3333");
+        assert_eq!(format_number(0.00001), "0");
+        assert_eq!(format_number(-0.00001), "0");
+        assert_eq!(format_number(-2.25), "-2.25");
+    }
+}
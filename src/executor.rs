@@ -1,48 +1,466 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
 use std::process::{Command, Stdio};
 use anyhow::Result;
+use directories::BaseDirs;
+use log::warn;
 use crate::model::Entry;
 use crate::config::Config;
 use crate::sources::history;
 
 pub fn execute(entry: &Entry, config: &Config, active_group: &str) -> Result<()> {
     // Increment usage history
-    let _ = history::increment_usage(&entry.id);
+    let _ = history::increment_usage(active_group, &entry.id, config.general.history_size);
 
-    // Basic execution logic with Terminal support
-    
-    let mut cmd_parts = Vec::new();
-    
-    if entry.open_in_terminal {
-        if let Some(term_cmd) = &config.general.terminal {
-             cmd_parts.extend(term_cmd.split_whitespace());
-             cmd_parts.push(&entry.command);
-        } else {
-             cmd_parts.extend(entry.command.split_whitespace());
+    if let Some(bus_name) = &entry.dbus_name {
+        match dbus_activate(bus_name) {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("DBus activation of {} failed, using Exec: {}", bus_name, e),
         }
-    } else {
-         cmd_parts.extend(entry.command.split_whitespace());
     }
 
-    if cmd_parts.is_empty() {
+    let Some((cmd_parts, group_env)) = command_line(entry, config, active_group) else {
         return Ok(());
-    }
+    };
 
-    let mut command = Command::new(cmd_parts[0]);
+    let mut command = Command::new(&cmd_parts[0]);
     command.args(&cmd_parts[1..])
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null());
 
     // Apply group env overrides
-    if let Some(group_config) = config.groups.get(active_group) {
-        if let Some(env) = &group_config.env {
-            for (key, val) in env {
-                command.env(key, val);
-            }
-        }
+    command.envs(&group_env);
+    
+    let mut child = command.spawn()?;
+    // Reap the child once it exits so a long-running daemon doesn't collect zombies
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    
+    Ok(())
+}
+
+/// Write the command `execute` would run to stdout, shell-quoted, instead of
+/// running it. Group env is not included.
+pub fn print(entry: &Entry, config: &Config, active_group: &str) -> Result<()> {
+    let _ = history::increment_usage(active_group, &entry.id, config.general.history_size);
+
+    if let Some((cmd_parts, _)) = command_line(entry, config, active_group) {
+        let quoted: Vec<String> = cmd_parts.iter().map(|p| shell_quote(p)).collect();
+        println!("{}", quoted.join(" "));
     }
+    Ok(())
+}
+
+/// The argv to spawn for `entry`, with launch prefix, terminal and variable
+/// expansion applied, and the group env to run it with. `None` if the entry
+/// has no command.
+fn command_line(entry: &Entry, config: &Config, active_group: &str) -> Option<(Vec<String>, HashMap<String, String>)> {
+    if entry.command.trim().is_empty() {
+        return None;
+    }
+
+    let group_env = config.groups.get(active_group)
+        .and_then(|g| g.env.as_ref())
+        .map(resolve_env)
+        .unwrap_or_default();
+    let mut lookup = |name: &str| group_env.get(name).cloned().or_else(|| std::env::var(name).ok());
+
+    // Basic execution logic with Terminal support
     
-    command.spawn()?;
+    let mut cmd_parts = split_expanded(&config.general.launch_prefix, &mut lookup);
     
+    if entry.open_in_terminal
+        && let Some(term_cmd) = &config.general.terminal {
+        cmd_parts.extend(split_expanded(term_cmd, &mut lookup));
+    }
+    // The terminal's -e takes the program and its arguments as separate words
+    cmd_parts.extend(split_expanded(&entry.command, &mut lookup));
+
+    Some((cmd_parts, group_env))
+}
+
+/// Split a command into arguments at unquoted whitespace, the way a POSIX
+/// shell would: `'...'` is taken literally, and in `"..."` or bare words a
+/// backslash escapes the next character (inside double quotes only `"`,
+/// `\`, `$` and `` ` ``). An unterminated quote runs to the end.
+pub fn split_command(command: &str) -> Vec<String> {
+    tokenize(command, None)
+}
+
+/// `split_command`, expanding variables where a shell would: `$VAR` and
+/// `${VAR}` in bare words and double quotes, `~` at the start of a bare word
+/// or after a bare `:`. Single-quoted or backslash-escaped text is left as
+/// is, and an expanded value never splits into more arguments.
+fn split_expanded(command: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> Vec<String> {
+    tokenize(command, Some(lookup))
+}
+
+/// Where variables are looked up while splitting a command
+type Lookup<'a> = &'a mut dyn FnMut(&str) -> Option<String>;
+
+fn tokenize(command: &str, mut lookup: Option<Lookup>) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // A quoted empty string ('') is still an argument
+    let mut in_arg = false;
+    let mut word_start = true;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                arg.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                in_arg = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => arg.push(escaped),
+                            Some(other) => {
+                                arg.push('\\');
+                                arg.push(other);
+                            }
+                            None => arg.push('\\'),
+                        },
+                        '$' => push_var(&mut arg, &mut chars, &mut lookup),
+                        _ => arg.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                arg.push(chars.next().unwrap_or('\\'));
+            }
+            '$' => {
+                in_arg = true;
+                push_var(&mut arg, &mut chars, &mut lookup);
+            }
+            '~' if word_start && lookup.is_some()
+                && chars.peek().is_none_or(|&n| n == '/' || n == ':' || n.is_whitespace()) => {
+                in_arg = true;
+                match BaseDirs::new() {
+                    Some(dirs) => arg.push_str(&dirs.home_dir().to_string_lossy()),
+                    None => arg.push('~'),
+                }
+            }
+            c if c.is_whitespace() => {
+                if std::mem::take(&mut in_arg) {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            _ => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+        word_start = c == ':' || c.is_whitespace();
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// Expand the variable after a `$` into `arg`, or keep the `$` if no name
+/// follows or there is nothing to look it up in.
+fn push_var(arg: &mut String, chars: &mut Peekable<Chars>, lookup: &mut Option<Lookup>) {
+    if let Some(lookup) = lookup.as_deref_mut()
+        && let Some(name) = take_var_name(chars) {
+        arg.push_str(&lookup(&name).unwrap_or_default());
+    } else {
+        arg.push('$');
+    }
+}
+
+/// The `NAME` or `{NAME}` following a `$`, consumed from `chars`. Consumes
+/// nothing if there is none.
+fn take_var_name(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut ahead = chars.clone();
+    let mut name = String::new();
+    if ahead.next_if_eq(&'{').is_some() {
+        loop {
+            match ahead.next()? {
+                '}' => break,
+                c if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                _ => return None,
+            }
+        }
+    } else {
+        while let Some(c) = ahead.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+            name.push(c);
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
+    *chars = ahead;
+    Some(name)
+}
+
+/// Quote `arg` for a POSIX shell, leaving plain words alone.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Expand `$VAR`, `${VAR}` and `~` in `input` the way commands are expanded
+/// at launch: group env first, then the process environment.
+pub fn expand_for_group(input: &str, group_env: Option<&HashMap<String, String>>) -> String {
+    let group_env = group_env.map(resolve_env).unwrap_or_default();
+    expand(input, &mut |name| group_env.get(name).cloned().or_else(|| std::env::var(name).ok()))
+}
+
+/// Ask the app to start (or surface its running instance) through the
+/// org.freedesktop.Application interface, as the desktop entry spec asks for
+/// `DBusActivatable=true`.
+fn dbus_activate(bus_name: &str) -> Result<()> {
+    let connection = zbus::blocking::Connection::session()?;
+    let object_path = format!("/{}", bus_name.replace('.', "/").replace('-', "_"));
+
+    let mut platform_data: HashMap<&str, zbus::zvariant::Value> = HashMap::new();
+    if let Ok(token) = std::env::var("XDG_ACTIVATION_TOKEN") {
+        platform_data.insert("activation-token", token.into());
+    }
+
+    connection.call_method(
+        Some(bus_name),
+        object_path.as_str(),
+        Some("org.freedesktop.Application"),
+        "Activate",
+        &(platform_data,),
+    )?;
     Ok(())
 }
+
+/// Expand every group env value. Values may reference other group variables
+/// (in any order) as well as the process environment, so
+/// `PATH = "$HOME/bin:$PATH"` extends the inherited PATH.
+fn resolve_env(env: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut resolved = HashMap::new();
+    for key in env.keys() {
+        resolve_var(key, env, &mut resolved, &mut Vec::new());
+    }
+    resolved
+}
+
+fn resolve_var(key: &str, env: &HashMap<String, String>, resolved: &mut HashMap<String, String>, stack: &mut Vec<String>) -> String {
+    if let Some(value) = resolved.get(key) {
+        return value.clone();
+    }
+
+    // A variable referring to itself (or a cycle) sees the inherited value
+    stack.push(key.to_string());
+    let value = expand(&env[key], &mut |name| {
+        if env.contains_key(name) && !stack.iter().any(|s| s == name) {
+            Some(resolve_var(name, env, resolved, stack))
+        } else {
+            std::env::var(name).ok()
+        }
+    });
+    stack.pop();
+
+    resolved.insert(key.to_string(), value.clone());
+    value
+}
+
+/// Expand `$VAR`, `${VAR}` and a leading `~` (at the start of a word or after
+/// `:`, so PATH-style lists work). Unknown variables expand to an empty string; a `$`
+/// not followed by a variable name is kept as is.
+fn expand(input: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.char_indices().peekable();
+    let mut word_start = true;
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '~' if word_start && chars.peek().is_none_or(|&(_, n)| n == '/' || n == ':' || n.is_whitespace()) => {
+                match BaseDirs::new() {
+                    Some(dirs) => out.push_str(&dirs.home_dir().to_string_lossy()),
+                    None => out.push('~'),
+                }
+            }
+            '$' => {
+                let rest = &input[i + 1..];
+                let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+                    match braced.find('}') {
+                        Some(end) => (&braced[..end], end + 2),
+                        None => ("", 0),
+                    }
+                } else {
+                    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                    (&rest[..end], end)
+                };
+
+                if name.is_empty() {
+                    out.push('$');
+                } else {
+                    out.push_str(&lookup(name).unwrap_or_default());
+                    while chars.next_if(|&(j, _)| j <= i + len).is_some() {}
+                }
+            }
+            _ => out.push(c),
+        }
+        word_start = c == ':' || c.is_whitespace();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::EntryType;
+
+    #[test]
+    fn split_command_follows_shell_quoting() {
+        assert_eq!(split_command(r#"a 'b c' "d \"e\"" f\ g '' "$x\y""#), vec!["a", "b c", "d \"e\"", "f g", "", "$x\\y"]);
+        assert_eq!(split_command("  spaced   out  "), vec!["spaced", "out"]);
+        assert_eq!(split_command("'unterminated quote"), vec!["unterminated quote"]);
+        assert!(split_command("   ").is_empty());
+    }
+
+    #[test]
+    fn shell_quote_leaves_plain_words_alone() {
+        assert_eq!(shell_quote("firefox"), "firefox");
+        assert_eq!(shell_quote("/usr/bin/a-b_c.sh"), "/usr/bin/a-b_c.sh");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shell_quote_round_trips_through_split_command() {
+        for arg in ["plain", "two words", "it's", "", "$HOME", "back\\slash", "\"quoted\""] {
+            assert_eq!(split_command(&shell_quote(arg)), vec![arg]);
+        }
+    }
+
+    fn expand_with(input: &str, vars: &[(&str, &str)]) -> String {
+        expand(input, &mut |name| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string()))
+    }
+
+    fn split_with(command: &str, vars: &[(&str, &str)]) -> Vec<String> {
+        split_expanded(command, &mut |name| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string()))
+    }
+
+    fn home() -> String {
+        BaseDirs::new().unwrap().home_dir().to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn expand_variables() {
+        let vars = [("A", "1"), ("LONG_NAME", "x y")];
+        assert_eq!(expand_with("$A-${A}b $LONG_NAME", &vars), "1-1b x y");
+        assert_eq!(expand_with("$Ab", &vars), "");
+        assert_eq!(expand_with("${MISSING}!", &vars), "!");
+        assert_eq!(expand_with("cost: 5$ $ ${", &vars), "cost: 5$ $ ${");
+    }
+
+    #[test]
+    fn expand_tilde_only_at_word_starts() {
+        let home = home();
+        assert_eq!(expand_with("~", &[]), home);
+        assert_eq!(expand_with("~/bin:~/.local/bin", &[]), format!("{0}/bin:{0}/.local/bin", home));
+        assert_eq!(expand_with("cd ~ && ls a~b ~user", &[]), format!("cd {} && ls a~b ~user", home));
+    }
+
+    #[test]
+    fn commands_expand_only_outside_single_quotes() {
+        let vars = [("A", "1"), ("SPACED", "x y")];
+        assert_eq!(split_with(r#"echo $A "$A-${A}" '$A' \$A "\$A""#, &vars), ["echo", "1", "1-1", "$A", "$A", "$A"]);
+        // Expanded values stay one argument, as if quoted
+        assert_eq!(split_with("ls $SPACED", &vars), ["ls", "x y"]);
+        assert_eq!(split_with("sh -c 'echo $1' -- $A", &vars), ["sh", "-c", "echo $1", "--", "1"]);
+        assert_eq!(split_with("cost 5$ $ ${ ${} ${A", &vars), ["cost", "5$", "$", "${", "${}", "${A"]);
+    }
+
+    #[test]
+    fn commands_expand_only_bare_tildes() {
+        let home = home();
+        assert_eq!(split_with("ls ~ ~/a ~/bin:~/sbin", &[]), ["ls".to_string(), home.clone(), format!("{}/a", home), format!("{0}/bin:{0}/sbin", home)]);
+        assert_eq!(split_with(r#"ls '~' "~/a" \~ a~b ~user"#, &[]), ["ls", "~", "~/a", "~", "a~b", "~user"]);
+    }
+
+    #[test]
+    fn group_env_values_see_each_other_and_the_inherited_value() {
+        let inherited = std::env::var("PATH").unwrap_or_default();
+        let env: HashMap<String, String> = [
+            ("PATH", "$BIN:$PATH"),
+            ("BIN", "$ROOT/bin"),
+            ("ROOT", "/opt/app"),
+            ("LOOP_A", "a$LOOP_B"),
+            ("LOOP_B", "b$LOOP_A"),
+        ].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        let resolved = resolve_env(&env);
+        assert_eq!(resolved["BIN"], "/opt/app/bin");
+        assert_eq!(resolved["PATH"], format!("/opt/app/bin:{}", inherited));
+        // A cycle falls back to the (unset) process variable instead of looping
+        assert!(resolved["LOOP_A"].starts_with('a'));
+        assert!(resolved["LOOP_B"].starts_with('b'));
+    }
+
+    #[test]
+    fn static_items_expand_with_the_group_env() {
+        let mut config = Config::default();
+        let env: HashMap<String, String> = [("TOOLS".to_string(), "~/tools".to_string())].into();
+        config.groups.get_mut("default").unwrap().env = Some(env.clone());
+
+        let item = Entry::new("custom:sync".into(), "sync".into(), "$TOOLS/sync --to ~/backup".into(), EntryType::Custom, false);
+        let (argv, group_env) = command_line(&item, &config, "default").unwrap();
+        let home = home();
+        assert_eq!(argv, [format!("{}/tools/sync", home), "--to".to_string(), format!("{}/backup", home)]);
+        assert_eq!(group_env["TOOLS"], format!("{}/tools", home));
+
+        assert_eq!(expand_for_group("$TOOLS/icon.png", Some(&env)), format!("{}/tools/icon.png", home));
+        assert_eq!(expand_for_group("~/icon.png", None), format!("{}/icon.png", home));
+    }
+
+    #[test]
+    fn desktop_exec_quoting_survives_to_the_argv() {
+        let config = Config::default();
+        // What `clean_exec` makes of `Exec=echo "\\$HOME"` and `Exec=sh -c "echo \\$1" %u`
+        for (command, expected) in [
+            ("echo '$HOME'", vec!["echo", "$HOME"]),
+            ("sh -c 'echo $1'", vec!["sh", "-c", "echo $1"]),
+            ("'/opt/My App/run' --x", vec!["/opt/My App/run", "--x"]),
+        ] {
+            let entry = Entry::new("app".into(), "app".into(), command.into(), EntryType::Desktop, false);
+            let (argv, _) = command_line(&entry, &config, "default").unwrap();
+            assert_eq!(argv, expected);
+        }
+    }
+
+    #[test]
+    fn launch_prefix_is_split_like_a_command() {
+        let mut config = Config::default();
+        config.general.launch_prefix = r#"systemd-run --user --scope --description "My App" --"#.to_string();
+        let env: HashMap<String, String> = [("MOZ_ENABLE_WAYLAND".to_string(), "1".to_string())].into();
+        config.groups.get_mut("default").unwrap().env = Some(env);
+        let entry = Entry::new("firefox".into(), "firefox".into(), "firefox --new-window".into(), EntryType::Binary, false);
+
+        let (argv, group_env) = command_line(&entry, &config, "default").unwrap();
+        assert_eq!(argv, ["systemd-run", "--user", "--scope", "--description", "My App", "--", "firefox", "--new-window"]);
+        // The prefix inherits the env, so the app does too
+        assert_eq!(group_env["MOZ_ENABLE_WAYLAND"], "1");
+    }
+
+    #[test]
+    fn terminal_commands_are_split_into_words() {
+        let mut config = Config::default();
+        config.general.terminal = Some("foot --title 'My Term' -e".to_string());
+        let entry = Entry::new("htop".into(), "htop".into(), "htop -d '5'".into(), EntryType::Binary, true);
+
+        let (argv, _) = command_line(&entry, &config, "default").unwrap();
+        assert_eq!(argv, vec!["foot", "--title", "My Term", "-e", "htop", "-d", "5"]);
+    }
+}
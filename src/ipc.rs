@@ -0,0 +1,88 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use log::{debug, warn};
+
+/// Messages a `runner --show`/`--quit` invocation sends to a running daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcCommand {
+    Show,
+    Quit,
+}
+
+impl IpcCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IpcCommand::Show => "show",
+            IpcCommand::Quit => "quit",
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "show" => Some(IpcCommand::Show),
+            "quit" => Some(IpcCommand::Quit),
+            _ => None,
+        }
+    }
+}
+
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("runner.sock"),
+        None => std::env::temp_dir().join(format!("runner-{}.sock", nix::unistd::getuid())),
+    }
+}
+
+/// Send `command` to the running daemon.
+pub fn send(command: IpcCommand) -> Result<()> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| anyhow!("No runner daemon is listening on {}: {}", path.display(), e))?;
+    writeln!(stream, "{}", command.as_str())?;
+    Ok(())
+}
+
+/// Listen for commands on the daemon socket, forwarding them to the event
+/// loop. Fails if another daemon is already listening.
+pub fn listen(tx: calloop::channel::Sender<IpcCommand>) -> Result<()> {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        return Err(anyhow!("A runner daemon is already running ({})", path.display()));
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("IPC connection failed: {}", e);
+                    continue;
+                }
+            };
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                match IpcCommand::parse(&line) {
+                    Some(command) => {
+                        debug!("IPC command: {:?}", command);
+                        if tx.send(command).is_err() {
+                            return;
+                        }
+                    }
+                    None => warn!("Unknown IPC command: {:?}", line),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Remove the daemon socket on shutdown.
+pub fn cleanup() {
+    let _ = fs::remove_file(socket_path());
+}
@@ -3,26 +3,37 @@ mod state;
 mod model;
 mod sources;
 mod matcher;
+mod convert;
 mod ui;
 mod executor;
+mod ipc;
+mod timings;
 
 use anyhow::Result;
 use calloop::EventLoop;
+use calloop::signals::{Signal, Signals};
 use calloop_wayland_source::WaylandSource;
-use smithay_client_toolkit::{
-    shell::wlr_layer::{Layer, KeyboardInteractivity, Anchor},
-    shell::WaylandSurface,
-};
 use wayland_client::{Connection, globals::registry_queue_init};
-use crate::config::load_config;
+use crate::config::{Config, config_path, load_config};
+use crate::ipc::IpcCommand;
 use crate::state::AppState;
 use crate::ui::wayland::WaylandApp;
 use crate::ui::render::Renderer;
-use crate::ui::icons::IconCache;
-use crate::sources::{Source, desktop::DesktopSource, bin::BinSource, scripts::ScriptsSource};
+use crate::ui::icons::{DiskCache, IconCache};
+use crate::matcher::MatchWorker;
 use crate::model::{Entry, EntryType};
+use crate::sources::Source;
+use crate::timings::Timings;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::Duration;
 use clap::Parser;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long source directories have to be quiet before a rescan, so an
+/// install touching many files triggers only one
+const RESCAN_DEBOUNCE: Duration = Duration::from_millis(500);
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -30,14 +41,104 @@ struct Args {
     /// Launch group to use
     #[arg(short, long, default_value = "default")]
     group: String,
+
+    /// Delete the launch history (and the remembered query) and exit
+    #[arg(long)]
+    clear_history: bool,
+
+    /// Reload the config file whenever it changes (handy while theming)
+    #[arg(long)]
+    watch_config: bool,
+
+    /// Read the config from this file instead of the default location
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Output (monitor) to show the launcher on, overriding theme.output
+    #[arg(long, value_name = "NAME")]
+    output: Option<String>,
+
+    /// Print the available outputs and exit
+    #[arg(long)]
+    list_outputs: bool,
+
+    /// Stay running in the background with sources loaded, showing the
+    /// launcher whenever `runner --show` is run
+    #[arg(long)]
+    daemon: bool,
+
+    /// Show the launcher of the running daemon
+    #[arg(long, conflicts_with = "daemon")]
+    show: bool,
+
+    /// Stop the running daemon
+    #[arg(long, conflicts_with_all = ["daemon", "show"])]
+    quit: bool,
+
+    /// Pick one of the lines read from stdin and print it, like dmenu.
+    /// Exits with status 1 if nothing was picked.
+    #[arg(long, conflicts_with = "daemon")]
+    dmenu: bool,
+
+    /// Print the selected entry's command to stdout instead of running it
+    #[arg(long, conflicts_with_all = ["daemon", "dmenu"])]
+    print: bool,
+
+    /// Open a regular window instead of an overlay. This is automatic on
+    /// compositors without the layer shell, like GNOME
+    #[arg(long)]
+    windowed: bool,
+
+    /// Let Tab mark several entries and Enter launch (or with --dmenu or
+    /// --print, output) all of them
+    #[arg(long)]
+    multi: bool,
+
+    /// Open with this query already typed in (and selected, so typing
+    /// replaces it)
+    #[arg(long, value_name = "STRING", conflicts_with_all = ["daemon", "show", "quit"])]
+    query: Option<String>,
+
+    /// Print how long loading the config, each source scan, the first
+    /// filter and the first paint took, to stderr
+    #[arg(long)]
+    timings: bool,
 }
 
 fn main() -> Result<()> {
     env_logger::init();
     let args = Args::parse();
+    let timings = Timings::new(args.timings);
+
+    if args.show || args.quit {
+        let command = if args.quit { IpcCommand::Quit } else { IpcCommand::Show };
+        if let Err(e) = ipc::send(command) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.clear_history {
+        if sources::history::clear()? {
+            println!("History cleared");
+        } else {
+            println!("History is already empty");
+        }
+        return Ok(());
+    }
 
     // 1. Load Config
-    let config = load_config()?;
+    let config = match timings.time("config load", || load_config(args.config.clone())) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: invalid config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !report_config_issues(&config) {
+        std::process::exit(1);
+    }
     
     // Validate group exists, fallback to default if not
     let group_name = if config.groups.contains_key(&args.group) {
@@ -50,44 +151,114 @@ fn main() -> Result<()> {
 
     // 2. Setup Wayland Connection & Event Loop
     let mut event_loop: EventLoop<WaylandApp> = EventLoop::try_new()?;
+
+    // Leave through the normal exit path on SIGTERM/SIGINT so the surface is
+    // torn down properly instead of vanishing with the process. This blocks
+    // the signals for the current thread, so it has to happen before any
+    // worker thread is spawned (they inherit the mask).
+    let signals = Signals::new(&[Signal::SIGTERM, Signal::SIGINT])?;
+    event_loop.handle().insert_source(signals, |event, _, app: &mut WaylandApp| {
+        log::info!("Received {:?}, exiting", event.signal());
+        app.should_exit = true;
+    }).unwrap();
+
     let conn = Connection::connect_to_env()?;
-    let (globals, event_queue) = registry_queue_init::<WaylandApp>(&conn).unwrap();
+    let (globals, mut event_queue) = registry_queue_init::<WaylandApp>(&conn).unwrap();
     let qh = event_queue.handle();
 
     // 3. Init State & UI
     let (tx_icons, rx_icons) = calloop::channel::channel::<(String, Option<tiny_skia::Pixmap>)>();
-    let icon_cache = IconCache::new(tx_icons);
+    let disk_cache = DiskCache::from_config(&config.general);
+    let icon_cache = IconCache::new(tx_icons, config.general.icon_cache_size, disk_cache, config.theme.icon_theme.clone());
     let renderer = Renderer::new(icon_cache);
 
-    let mut app_state = AppState::new(config.clone());
+    let (tx_matches, rx_matches) = calloop::channel::channel();
+    let match_worker = MatchWorker::new(tx_matches);
+
+    let mut app_state = AppState::new(config.clone(), match_worker);
     app_state.active_group = group_name; 
-    let mut app = WaylandApp::new(&conn, &globals, &qh, app_state, renderer);
+    app_state.dmenu = args.dmenu;
+    app_state.multi = args.multi;
+    app_state.restore_query();
+    if let Some(query) = args.query.as_deref() {
+        app_state.prefill_query(query);
+    }
+    let mut app = match WaylandApp::new(&conn, &globals, &qh, event_loop.handle(), app_state, renderer) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Receive output names and geometry before picking one
+    event_queue.roundtrip(&mut app)?;
+
+    if args.list_outputs {
+        for info in app.output_state.outputs().filter_map(|o| app.output_state.info(&o)) {
+            let size = info.logical_size.map(|(w, h)| format!("{}x{}", w, h)).unwrap_or_default();
+            println!("{}\t{}\t{}", info.name.unwrap_or_default(), size, info.description.unwrap_or_default());
+        }
+        return Ok(());
+    }
 
     // 4. Create Layer Surface
-    let surface = app.compositor_state.create_surface(&qh);
-    let layer_surface = app.layer_shell_state.create_layer_surface(
-        &qh,
-        surface,
-        Layer::Overlay,
-        Some("runner"),
-        None,
-    );
-    
-    layer_surface.set_anchor(Anchor::empty()); 
-    layer_surface.set_size(config.theme.width, config.theme.height);
-    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
-    layer_surface.commit(); 
-    app.layer_surface = Some(layer_surface);
+    app.output_name = args.output.clone().or(config.theme.output.clone());
+    app.daemon = args.daemon;
+    app.print_only = args.print;
+    app.windowed = args.windowed;
+    app.paint_timings = Some(timings);
+    if args.daemon {
+        // Start hidden; `runner --show` maps the surface
+        let (tx_ipc, rx_ipc) = calloop::channel::channel();
+        ipc::listen(tx_ipc)?;
+        let qh_ipc = qh.clone();
+        event_loop.handle().insert_source(rx_ipc, move |event, _, app: &mut WaylandApp| {
+            if let calloop::channel::Event::Msg(command) = event {
+                match command {
+                    IpcCommand::Show => {
+                        if app.surface.is_none() {
+                            app.state.reset();
+                        }
+                        app.show(&qh_ipc);
+                    }
+                    IpcCommand::Quit => app.should_exit = true,
+                }
+            }
+        }).unwrap();
+    } else {
+        app.show(&qh);
+    }
 
     // 5. Spawn Source Loader based on Group config
     let (tx_entries, rx_entries) = calloop::channel::channel();
-    let sources_to_scan = group_config.sources.clone();
+    let sources_to_scan: Vec<String> = group_config.sources.iter()
+        .filter(|source| {
+            let enabled = config.sources.enabled(source);
+            if !enabled {
+                log::debug!("Skipping source {}, switched off in [sources]", source);
+            }
+            enabled
+        })
+        .cloned()
+        .collect();
     let static_items = group_config.items.clone();
+    let group_env = group_config.env.clone();
+    let source_config = config.clone();
+    let watched_sources = sources_to_scan.clone();
+    let dmenu = args.dmenu;
     
+    // Static items show up right away; sources follow as their scans finish.
     thread::spawn(move || {
+        if dmenu {
+            sources::stdin::stream_lines(tx_entries);
+            return;
+        }
+
         let mut entries = Vec::new();
         
-        // Add static items
+        // Add static items. Commands are expanded at launch; icons have to be
+        // expanded here so `~/...` reaches the icon loader as an absolute path.
         for item in static_items {
             let mut entry = Entry::new(
                 format!("custom:{}", item.name),
@@ -96,27 +267,35 @@ fn main() -> Result<()> {
                 EntryType::Custom,
                 item.terminal,
             );
-            entry.icon = item.icon;
+            entry.icon = item.icon.map(|icon| executor::expand_for_group(&icon, group_env.as_ref()));
             entries.push(entry);
         }
+        if !entries.is_empty() {
+            let _ = tx_entries.send(entries);
+        }
+
+        // Scan every source on its own thread, but hand the results over in
+        // the group's source order so equal-scoring matches rank the same
+        // way on every run
+        thread::scope(|scope| {
+            let scans: Vec<_> = sources_to_scan.iter()
+                .filter_map(|name| sources::by_name(name, &source_config).map(|source| (name, source)))
+                .map(|(name, source)| {
+                    let step = format!("scan {}", name);
+                    (name, scope.spawn(move || timings.time(&step, || sources::scan(source.as_ref()))))
+                })
+                .collect();
 
-        // Only scan if the source is in the group's source list
-        if sources_to_scan.contains(&"desktop".to_string()) {
-            if let Ok(mut e) = DesktopSource.scan() {
-                 entries.append(&mut e);
+            for (name, scan) in scans {
+                match scan.join() {
+                    Ok(Ok(e)) => {
+                        let _ = tx_entries.send(e);
+                    }
+                    Ok(Err(e)) => log::warn!("Scanning {} failed: {}", name, e),
+                    Err(_) => log::warn!("Scanning {} panicked", name),
+                }
             }
-        }
-        if sources_to_scan.contains(&"bin".to_string()) {
-             if let Ok(mut e) = BinSource.scan() {
-                 entries.append(&mut e);
-             }
-        }
-        if sources_to_scan.contains(&"scripts".to_string()) {
-             if let Ok(mut e) = ScriptsSource.scan() {
-                 entries.append(&mut e);
-             }
-        }
-        let _ = tx_entries.send(entries);
+        });
     });
 
     let conn_clone = conn.clone();
@@ -135,13 +314,77 @@ fn main() -> Result<()> {
     // Entry loader handler
     let conn_c2 = conn_clone.clone();
     let qh_c2 = qh_clone.clone();
+    let mut first_filter = true;
     event_loop.handle().insert_source(rx_entries, move |event, _, app: &mut WaylandApp| {
         if let calloop::channel::Event::Msg(entries) = event {
-            app.state.set_entries(entries);
+            if std::mem::take(&mut first_filter) {
+                timings.time("first filter", || app.state.add_entries(entries));
+            } else {
+                app.state.add_entries(entries);
+            }
             app.draw(&conn_c2, &qh_c2);
         }
     }).unwrap();
     
+    // Rescans of sources whose directories changed, e.g. a newly installed
+    // app. The watcher has to stay alive for the whole run.
+    let _source_watcher = if args.dmenu {
+        None
+    } else {
+        let (tx_rescan, rx_rescan) = calloop::channel::channel::<(String, Vec<Entry>)>();
+        let conn_c5 = conn_clone.clone();
+        let qh_c5 = qh_clone.clone();
+        event_loop.handle().insert_source(rx_rescan, move |event, _, app: &mut WaylandApp| {
+            if let calloop::channel::Event::Msg((source, entries)) = event {
+                log::info!("Rescanned {} after changes on disk", source);
+                app.state.replace_source(&source, entries);
+                app.draw(&conn_c5, &qh_c5);
+            }
+        }).unwrap();
+        watch_sources(&watched_sources, &config, tx_rescan)
+            .inspect_err(|e| log::warn!("Not watching sources for changes: {}", e))
+            .ok()
+    };
+
+    // Config reload handler. The watcher has to stay alive for the whole run.
+    let _config_watcher = if args.watch_config {
+        let (tx_config, rx_config) = calloop::channel::channel();
+        let path = args.config.clone().unwrap_or_else(config_path);
+        let watcher = watch_config(&path, tx_config)
+            .inspect_err(|e| log::warn!("Not watching config for changes: {}", e))
+            .ok();
+        let conn_c4 = conn_clone.clone();
+        let qh_c4 = qh_clone.clone();
+        event_loop.handle().insert_source(rx_config, move |event, _, app: &mut WaylandApp| {
+            if let calloop::channel::Event::Msg(()) = event {
+                match load_config(Some(path.clone())) {
+                    Ok(config) if !report_config_issues(&config) => {
+                        log::error!("Keeping previous config, the new one has errors");
+                    }
+                    Ok(config) => {
+                        log::info!("Config reloaded");
+                        app.apply_config(config);
+                        app.draw(&conn_c4, &qh_c4);
+                    }
+                    Err(e) => log::error!("Keeping previous config, failed to reload: {}", e),
+                }
+            }
+        }).unwrap();
+        watcher
+    } else {
+        None
+    };
+
+    // Match result handler
+    let conn_c3 = conn_clone.clone();
+    let qh_c3 = qh_clone.clone();
+    event_loop.handle().insert_source(rx_matches, move |event, _, app: &mut WaylandApp| {
+        if let calloop::channel::Event::Msg(result) = event
+            && app.state.handle_match_result(result) {
+            app.draw(&conn_c3, &qh_c3);
+        }
+    }).unwrap();
+
     event_loop.handle().insert_source(
         WaylandSource::new(conn.clone(), event_queue),
         |_, queue, app| {
@@ -157,5 +400,101 @@ fn main() -> Result<()> {
         event_loop.dispatch(None, &mut app)?;
     }
 
+    // History is written synchronously on launch, so only the surface is left
+    app.hide();
+    let _ = conn.flush();
+
+    if args.daemon {
+        ipc::cleanup();
+    }
+    if args.dmenu {
+        if app.dmenu_selection.is_empty() {
+            std::process::exit(1);
+        }
+        for line in &app.dmenu_selection {
+            println!("{}", line);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Print config problems to stderr. Returns false if any of them is fatal.
+fn report_config_issues(config: &Config) -> bool {
+    let issues = config.validate();
+    for issue in &issues {
+        let level = if issue.fatal { "error" } else { "warning" };
+        eprintln!("{}: config: {}", level, issue.message);
+    }
+    !issues.iter().any(|i| i.fatal)
+}
+
+/// Watch the config file's directory (editors often replace the file rather
+/// than writing it in place) and signal whenever the config file changes.
+fn watch_config(path: &Path, tx: calloop::channel::Sender<()>) -> Result<RecommendedWatcher> {
+    let dir = path.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && !event.kind.is_access()
+            && event.paths.iter().any(|p| p.file_name().map(|n| n.to_os_string()) == file_name) {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Watch the directories of the sources in `names`, sending a fresh scan of
+/// a source once changes to its directories settle.
+fn watch_sources(
+    names: &[String],
+    config: &Config,
+    tx: calloop::channel::Sender<(String, Vec<Entry>)>,
+) -> Result<RecommendedWatcher> {
+    let sources: Vec<Box<dyn Source + Send>> = names.iter()
+        .filter_map(|name| sources::by_name(name, config))
+        .collect();
+    // Directories that don't exist yet (e.g. no scripts dir) aren't watched
+    let dirs: Vec<(PathBuf, usize)> = sources.iter().enumerate()
+        .flat_map(|(i, source)| source.watch_dirs().into_iter().filter(|d| d.is_dir()).map(move |d| (d, i)))
+        .collect();
+
+    let (tx_changed, rx_changed) = std::sync::mpsc::channel();
+    let watched = dirs.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res && !event.kind.is_access() {
+            for (dir, i) in &watched {
+                if event.paths.iter().any(|p| p.starts_with(dir)) {
+                    let _ = tx_changed.send(*i);
+                }
+            }
+        }
+    })?;
+    for (dir, _) in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    thread::spawn(move || {
+        while let Ok(first) = rx_changed.recv() {
+            let mut changed = HashSet::from([first]);
+            while let Ok(i) = rx_changed.recv_timeout(RESCAN_DEBOUNCE) {
+                changed.insert(i);
+            }
+            for i in changed {
+                match sources::scan(sources[i].as_ref()) {
+                    Ok(entries) => {
+                        if tx.send((sources[i].name().to_string(), entries)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => log::warn!("Rescanning {} failed: {}", sources[i].name(), e),
+                }
+            }
+        }
+    });
+    Ok(watcher)
+}
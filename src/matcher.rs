@@ -1,5 +1,11 @@
+use crate::config::MatchMode;
 use crate::model::Entry;
-use nucleo_matcher::{Matcher, Utf32Str};
+use nucleo_matcher::{Matcher, Utf32Str, chars};
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
 
 pub struct FuzzyMatcher {
     matcher: Matcher,
@@ -18,24 +24,218 @@ impl FuzzyMatcher {
         }
     }
 
-    pub fn match_entries(&mut self, query: &str, entries: &mut [Entry]) {
-        let pattern = nucleo_matcher::pattern::Pattern::parse(query, nucleo_matcher::pattern::CaseMatching::Smart, nucleo_matcher::pattern::Normalization::Smart);
-        
-        let mut buf = Vec::new(); // Reusable buffer if needed, though for simple scoring we might just loop
-
-        // Nucleo is designed for large lists, we can use score_pattern for one-off or implement the full pattern matching
-        // For simplicity here, we iterate and score.
-        
-        for entry in entries.iter_mut() {
-            let haystack = Utf32Str::new(&entry.name, &mut buf);
-            if let Some(score) = pattern.score(haystack, &mut self.matcher) {
-                entry.score = score as i64;
-            } else {
-                entry.score = -1;
+    /// Scores every entry against `query` and returns `(index, score)` for the
+    /// entries that matched. Ordering is left to the caller.
+    ///
+    /// The query is split on whitespace into atoms that must *all* match, in
+    /// any order: "dev fire" matches "Firefox Developer Edition". Matching is
+    /// case-insensitive unless the query contains an uppercase letter, and
+    /// accented letters in names match their unaccented form ("uber" finds
+    /// "Über", in every mode) unless the query has accents itself.
+    /// fzf-style atom modifiers (`^foo`, `'foo`, `!foo`, `foo$`) work.
+    pub fn match_entries(&mut self, query: &str, entries: &[Entry], mode: MatchMode) -> Vec<(usize, i64)> {
+        match mode {
+            MatchMode::Fuzzy => self.match_fuzzy(query, entries),
+            MatchMode::Prefix | MatchMode::Substring => match_literal(query, entries, mode),
+        }
+    }
+
+    fn match_fuzzy(&mut self, query: &str, entries: &[Entry]) -> Vec<(usize, i64)> {
+        let pattern = Pattern::parse(query, CaseMatching::Smart, Normalization::Smart);
+        let fold = folds_accents(query);
+
+        let mut buf = Vec::new();
+        let mut matches = Vec::new();
+
+        for (i, entry) in entries.iter().enumerate() {
+            // nucleo folds precomposed letters itself but not a letter
+            // followed by a combining accent
+            let name = if fold { strip_combining(&entry.name) } else { Cow::Borrowed(entry.name.as_str()) };
+            let haystack = Utf32Str::new(&name, &mut buf);
+            if let Some(score) = pattern.score(haystack, &mut self.matcher)
+                && score > 0 {
+                matches.push((i, score as i64));
             }
         }
-        
-        // Filter out non-matches and sort
-        // Note: The caller should filter entries with score < 0
+
+        matches
+    }
+}
+
+/// Plain prefix/substring matching over the whole query, bypassing nucleo.
+/// Uses the same smart-case and accent rules as fuzzy mode. Earlier matches
+/// score higher, then shorter names, so exact names rise to the top.
+fn match_literal(query: &str, entries: &[Entry], mode: MatchMode) -> Vec<(usize, i64)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let needle = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+    let fold = folds_accents(query);
+
+    entries.iter().enumerate().filter_map(|(i, entry)| {
+        let name = if fold { fold_accents(&entry.name) } else { Cow::Borrowed(entry.name.as_str()) };
+        let haystack = if case_sensitive { name.into_owned() } else { name.to_lowercase() };
+        let position = match mode {
+            MatchMode::Prefix => haystack.starts_with(&needle).then_some(0),
+            _ => haystack.find(&needle),
+        }?;
+        let extra_len = haystack.len().saturating_sub(needle.len());
+        let score = 10_000 - (position as i64) * 100 - extra_len as i64;
+        Some((i, score.max(1)))
+    }).collect()
+}
+
+/// Whether names should have their accents folded for `query`: only when
+/// the query has none itself, so "café" still finds only "Café".
+fn folds_accents(query: &str) -> bool {
+    query.chars().all(|c| chars::normalize(c) == c && !is_combining(c))
+}
+
+/// Combining diacritical marks, as in a decomposed "e\u{301}"
+fn is_combining(c: char) -> bool {
+    ('\u{300}'..='\u{36f}').contains(&c)
+}
+
+fn strip_combining(text: &str) -> Cow<'_, str> {
+    if text.chars().any(is_combining) {
+        Cow::Owned(text.chars().filter(|&c| !is_combining(c)).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// `text` with accented Latin letters replaced by their plain form, the
+/// way nucleo normalizes names in fuzzy mode.
+fn fold_accents(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(strip_combining(text).chars().map(chars::normalize).collect())
+}
+
+pub struct MatchRequest {
+    pub generation: u64,
+    pub query: String,
+    pub entries: Arc<Vec<Entry>>,
+    pub mode: MatchMode,
+}
+
+pub struct MatchResult {
+    pub generation: u64,
+    pub matches: Vec<(usize, i64)>,
+}
+
+/// Runs fuzzy matching on a background thread so long filters over huge
+/// entry lists don't block the Wayland event loop. Results are delivered
+/// through a calloop channel, tagged with the generation they were asked for.
+pub struct MatchWorker {
+    request_tx: Sender<MatchRequest>,
+}
+
+impl MatchWorker {
+    pub fn new(response_tx: calloop::channel::Sender<MatchResult>) -> Self {
+        let (request_tx, request_rx) = channel::<MatchRequest>();
+
+        thread::spawn(move || {
+            let mut matcher = FuzzyMatcher::new();
+            while let Ok(mut request) = request_rx.recv() {
+                // Skip straight to the newest query if the user kept typing
+                while let Ok(newer) = request_rx.try_recv() {
+                    request = newer;
+                }
+
+                let matches = matcher.match_entries(&request.query, &request.entries, request.mode);
+                if response_tx.send(MatchResult { generation: request.generation, matches }).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { request_tx }
+    }
+
+    pub fn request(&self, generation: u64, query: &str, entries: &Arc<Vec<Entry>>, mode: MatchMode) {
+        let _ = self.request_tx.send(MatchRequest {
+            generation,
+            query: query.to_string(),
+            entries: Arc::clone(entries),
+            mode,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::EntryType;
+
+    fn entries(names: &[&str]) -> Vec<Entry> {
+        names.iter()
+            .map(|name| Entry::new(name.to_string(), name.to_string(), String::new(), EntryType::Binary, false))
+            .collect()
+    }
+
+    fn matched(query: &str, names: &[&str], mode: MatchMode) -> Vec<String> {
+        let entries = entries(names);
+        let mut indices: Vec<usize> = FuzzyMatcher::new().match_entries(query, &entries, mode)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        indices.sort_unstable();
+        indices.into_iter().map(|i| entries[i].name.clone()).collect()
+    }
+
+    #[test]
+    fn every_term_must_match_in_any_order() {
+        let names = ["Firefox Developer Edition", "Firefox", "Developer Tools"];
+        assert_eq!(matched("dev fire", &names, MatchMode::Fuzzy), ["Firefox Developer Edition"]);
+        assert_eq!(matched("fire dev", &names, MatchMode::Fuzzy), ["Firefox Developer Edition"]);
+        assert_eq!(matched("  fire   dev  ", &names, MatchMode::Fuzzy), ["Firefox Developer Edition"]);
+        assert_eq!(matched("fire", &names, MatchMode::Fuzzy), ["Firefox Developer Edition", "Firefox"]);
+        assert!(matched("fire zzz", &names, MatchMode::Fuzzy).is_empty());
+    }
+
+    #[test]
+    fn terms_take_fzf_modifiers() {
+        let names = ["Firefox Developer Edition", "Firefox", "Developer Tools"];
+        assert_eq!(matched("^dev tools$", &names, MatchMode::Fuzzy), ["Developer Tools"]);
+        assert_eq!(matched("fire !edition", &names, MatchMode::Fuzzy), ["Firefox"]);
+    }
+
+    #[test]
+    fn smart_case() {
+        let names = ["firefox", "Firefox"];
+        assert_eq!(matched("fire", &names, MatchMode::Fuzzy), ["firefox", "Firefox"]);
+        assert_eq!(matched("Fire", &names, MatchMode::Fuzzy), ["Firefox"]);
+        assert_eq!(matched("Fire", &names, MatchMode::Substring), ["Firefox"]);
+    }
+
+    #[test]
+    fn prefix_and_substring_modes_match_the_whole_query() {
+        let names = ["Text Editor", "Editor", "Kate", "tex"];
+        assert_eq!(matched("edit", &names, MatchMode::Prefix), ["Editor"]);
+        assert_eq!(matched("edit", &names, MatchMode::Substring), ["Text Editor", "Editor"]);
+        // Spaces are part of the needle rather than separating terms
+        assert_eq!(matched("t ed", &names, MatchMode::Substring), ["Text Editor"]);
+        assert!(matched("edt", &names, MatchMode::Substring).is_empty());
+    }
+
+    #[test]
+    fn literal_matches_rank_earlier_then_shorter() {
+        let entries = entries(&["Text Editor", "Editor", "Editors"]);
+        let mut scored = match_literal("edit", &entries, MatchMode::Substring);
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        let order: Vec<&str> = scored.iter().map(|&(i, _)| entries[i].name.as_str()).collect();
+        assert_eq!(order, ["Editor", "Editors", "Text Editor"]);
+        assert!(scored.iter().all(|&(_, score)| score > 0));
+    }
+
+    #[test]
+    fn accents_fold_unless_the_query_has_them() {
+        let names = ["Über", "Cafe\u{301} Noir", "Café"];
+        for mode in [MatchMode::Fuzzy, MatchMode::Substring] {
+            assert_eq!(matched("uber", &names, mode), ["Über"]);
+            assert_eq!(matched("cafe", &names, mode), ["Cafe\u{301} Noir", "Café"]);
+            assert_eq!(matched("café", &names, mode), ["Café"]);
+        }
+        assert_eq!(matched("ub", &names, MatchMode::Prefix), ["Über"]);
     }
 }
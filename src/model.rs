@@ -5,6 +5,66 @@ pub enum EntryType {
     Binary,
     History,
     Custom,
+    /// A line read from stdin in `--dmenu` mode
+    Line,
+    /// A document opened with `xdg-open`
+    File,
+    /// A `Type=Link` desktop file's URL, opened with `xdg-open`
+    Link,
+}
+
+impl EntryType {
+    /// Names a group's `include_types`/`exclude_types` may use
+    pub const NAMES: &'static [&'static str] = &["desktop", "binary", "custom", "file", "link", "history"];
+
+    /// The type for a name from `NAMES` ("bin" works too).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "desktop" => Some(EntryType::Desktop),
+            "binary" | "bin" => Some(EntryType::Binary),
+            "custom" => Some(EntryType::Custom),
+            "file" => Some(EntryType::File),
+            "link" => Some(EntryType::Link),
+            "history" => Some(EntryType::History),
+            _ => None,
+        }
+    }
+
+    /// Lowercase name, as shown by `show_detail`
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryType::Desktop => "desktop",
+            EntryType::Binary => "binary",
+            EntryType::History => "history",
+            EntryType::Custom => "custom",
+            EntryType::Line => "line",
+            EntryType::File => "file",
+            EntryType::Link => "link",
+        }
+    }
+
+    /// Short label for the badge `type_colors` puts before the name
+    pub fn badge(&self) -> &'static str {
+        match self {
+            EntryType::Desktop => "app",
+            EntryType::Binary => "bin",
+            EntryType::History => "hist",
+            EntryType::Custom => "cmd",
+            EntryType::Line => "line",
+            EntryType::File => "file",
+            EntryType::Link => "link",
+        }
+    }
+
+    /// Generic theme icon for entries whose own icon is missing or broken
+    pub fn fallback_icon(&self) -> &'static str {
+        match self {
+            EntryType::Binary => "utilities-terminal",
+            EntryType::File => "text-x-generic",
+            EntryType::Link => "text-html",
+            _ => "application-x-executable",
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -19,6 +79,22 @@ pub struct Entry {
     pub is_container: bool,    // Context hint
     pub open_in_terminal: bool,
     pub entry_type: EntryType,
+    /// Well-known bus name for apps launched via DBus activation instead of
+    /// their Exec line (`DBusActivatable=true`)
+    pub dbus_name: Option<String>,
+    /// Secondary text drawn after the name, e.g. a file's full path
+    pub description: Option<String>,
+    /// Seconds since the epoch the item was last used, for sources that list
+    /// things by recency. Breaks ties before the name when sorting.
+    pub modified: Option<u64>,
+    /// Desktop entry `Categories`, e.g. ["Network", "WebBrowser"]
+    pub categories: Vec<String>,
+    /// Desktop entry `StartupWMClass`: the app_id/WM class its windows
+    /// get, when that differs from the desktop file id
+    pub wm_class: Option<String>,
+    /// Name of the source that produced it, empty for static items and
+    /// stdin lines
+    pub source: String,
 }
 
 impl Entry {
@@ -33,6 +109,19 @@ impl Entry {
             is_container: false,
             open_in_terminal,
             entry_type,
+            dbus_name: None,
+            description: None,
+            modified: None,
+            categories: Vec::new(),
+            wm_class: None,
+            source: String::new(),
         }
     }
+
+    /// Basename of the program the command runs, e.g. "firefox" for
+    /// "/usr/lib/firefox/firefox --new-window".
+    pub fn executable_name(&self) -> Option<String> {
+        let program = crate::executor::split_command(&self.command).into_iter().next()?;
+        program.rsplit('/').next().map(str::to_string)
+    }
 }
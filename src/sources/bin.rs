@@ -1,6 +1,8 @@
+use crate::executor::shell_quote;
 use crate::model::{Entry, EntryType};
 use crate::sources::Source;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
@@ -9,38 +11,97 @@ use log::{info, debug};
 pub struct BinSource;
 
 impl Source for BinSource {
+    fn name(&self) -> &str {
+        "bin"
+    }
+
     fn scan(&self) -> Result<Vec<Entry>> {
-        let mut entries = Vec::new();
-        if let Ok(path_var) = env::var("PATH") {
-            for path_str in path_var.split(':') {
-                let path = std::path::Path::new(path_str);
-                if path.exists() {
-                    debug!("Scanning binaries in {:?}", path);
-                    if let Ok(read_dir) = fs::read_dir(path) {
-                        for entry in read_dir.flatten() {
-                            let path = entry.path();
-                            if path.is_file() {
-                                if let Ok(metadata) = fs::metadata(&path) {
-                                    // Check if executable
-                                    if metadata.permissions().mode() & 0o111 != 0 {
-                                         if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
-                                             entries.push(Entry::new(
-                                                 path.to_string_lossy().to_string(),
-                                                 file_name.to_string(),
-                                                 path.to_string_lossy().to_string(),
-                                                 EntryType::Binary,
-                                                 false,
-                                             ));
-                                         }
-                                    }
-                                }
-                            }
-                        }
+        let entries = env::var("PATH").map(|path_var| scan_path(&path_var)).unwrap_or_default();
+        info!("BinSource: found {} entries", entries.len());
+        Ok(entries)
+    }
+}
+
+/// Executables in the directories of a `$PATH`-style list, each name once.
+fn scan_path(path_var: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    // First occurrence of a name wins, matching shell $PATH resolution
+    let mut seen_names = HashSet::new();
+    // Directories already scanned, e.g. /bin when it links to /usr/bin
+    let mut seen_dirs = HashSet::new();
+
+    for path_str in path_var.split(':') {
+        let path = std::path::Path::new(path_str);
+        if path.exists() {
+            let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            if !seen_dirs.insert(canonical) {
+                debug!("Skipping already scanned directory {:?}", path);
+                continue;
+            }
+
+            debug!("Scanning binaries in {:?}", path);
+            if let Ok(read_dir) = fs::read_dir(path) {
+                for entry in read_dir.flatten() {
+                    let path = entry.path();
+                    // Only regular files with an executable bit set
+                    if path.is_file()
+                        && let Ok(metadata) = fs::metadata(&path)
+                        && metadata.permissions().mode() & 0o111 != 0
+                        && let Some(file_name) = path.file_name().and_then(|s| s.to_str())
+                        && seen_names.insert(file_name.to_string()) {
+                        entries.push(Entry::new(
+                            path.to_string_lossy().to_string(),
+                            file_name.to_string(),
+                            shell_quote(&path.to_string_lossy()),
+                            EntryType::Binary,
+                            false,
+                        ));
                     }
                 }
             }
         }
-        info!("BinSource: found {} entries", entries.len());
-        Ok(entries)
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("runner-bin-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path, mode: u32) {
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn first_path_directory_wins() {
+        let root = temp_dir("dedupe");
+        let (first, second) = (root.join("first"), root.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        touch(&first.join("tool"), 0o755);
+        touch(&second.join("tool"), 0o755);
+        touch(&second.join("other"), 0o755);
+        touch(&second.join("readme"), 0o644);
+        // The same directory again under another name
+        std::os::unix::fs::symlink(&second, root.join("alias")).unwrap();
+
+        let path_var = format!("{}:{}:{}:/nonexistent", first.display(), second.display(), root.join("alias").display());
+        let mut found: Vec<(String, String)> = scan_path(&path_var).into_iter().map(|e| (e.name, e.id)).collect();
+        found.sort();
+        assert_eq!(found, [
+            ("other".to_string(), second.join("other").to_string_lossy().to_string()),
+            ("tool".to_string(), first.join("tool").to_string_lossy().to_string()),
+        ]);
+
+        let _ = fs::remove_dir_all(&root);
     }
 }
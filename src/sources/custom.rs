@@ -0,0 +1,155 @@
+use crate::config::CustomSourceConfig;
+use crate::model::{Entry, EntryType};
+use crate::sources::Source;
+use anyhow::{Result, bail};
+use std::process::{Command, Stdio};
+use log::{info, debug};
+
+/// Columns a custom source's `format` may list
+pub const COLUMNS: &[&str] = &["name", "command", "icon", "terminal", "description"];
+
+/// Entries read from the output of a user-configured command, one per line,
+/// with tab-separated columns in the order given by `format`.
+///
+/// A source with `script = true` is a drill-down menu: picking one of its
+/// entries runs the command again, with the names picked so far as `$1`,
+/// `$2`, ..., and its output becomes the next list. The command also gets
+/// `RUNNER_DEPTH` (how many names were passed, 0 for the first listing) and
+/// `RUNNER_INFO` (the picked line's `command` column, which script sources
+/// don't run themselves). Printing nothing ends the menu.
+pub struct CustomSource {
+    pub config: CustomSourceConfig,
+}
+
+impl Source for CustomSource {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn scan(&self) -> Result<Vec<Entry>> {
+        let entries = run(&self.config, &[], "")?;
+        info!("CustomSource {}: found {} entries", self.config.name, entries.len());
+        Ok(entries)
+    }
+}
+
+/// The next level of a script source's menu after picking `selections`
+/// (outermost first), the last of which had `info` in its command column.
+pub fn select(config: &CustomSourceConfig, selections: &[String], info: &str) -> Result<Vec<Entry>> {
+    let entries = run(config, selections, info)?;
+    debug!("CustomSource {}: {} entries at depth {}", config.name, entries.len(), selections.len());
+    Ok(entries)
+}
+
+fn run(config: &CustomSourceConfig, selections: &[String], info: &str) -> Result<Vec<Entry>> {
+    // The source name fills $0, so selections start at $1
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&config.command)
+        .arg(&config.name)
+        .args(selections)
+        .env("RUNNER_DEPTH", selections.len().to_string())
+        .env("RUNNER_INFO", info)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        bail!("{:?} exited with {}", config.command, output.status);
+    }
+
+    let columns: Vec<&str> = config.format.split('\t').map(str::trim).collect();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut entries = Vec::new();
+    for (line_no, line) in stdout.lines().enumerate() {
+        if line.trim().is_empty() { continue; }
+        match parse_line(line, &columns, &config.name) {
+            Some(mut entry) => {
+                entry.source = config.name.clone();
+                entries.push(entry);
+            }
+            None => debug!("{}: skipping line {} without a name: {:?}", config.name, line_no + 1, line),
+        }
+    }
+    Ok(entries)
+}
+
+/// One output line as an entry. Missing trailing columns are left empty;
+/// a line needs at least a name.
+fn parse_line(line: &str, columns: &[&str], source: &str) -> Option<Entry> {
+    let mut name = None;
+    let mut command = String::new();
+    let mut icon = None;
+    let mut terminal = false;
+    let mut description = None;
+
+    for (column, value) in columns.iter().zip(line.split('\t')) {
+        let value = value.trim();
+        if value.is_empty() { continue; }
+        match *column {
+            "name" => name = Some(value.to_string()),
+            "command" => command = value.to_string(),
+            "icon" => icon = Some(value.to_string()),
+            "terminal" => terminal = value == "true",
+            "description" => description = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let name = name?;
+    let mut entry = Entry::new(format!("{}:{}", source, name), name, command, EntryType::Custom, terminal);
+    entry.icon = icon;
+    entry.description = description;
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script_source(command: &str) -> CustomSourceConfig {
+        CustomSourceConfig {
+            name: "menu".to_string(),
+            command: command.to_string(),
+            format: "name\tcommand".to_string(),
+            script: true,
+        }
+    }
+
+    fn names(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn script_menu_descends_two_levels() {
+        let source = script_source(r#"
+            case "$RUNNER_DEPTH" in
+                0) printf 'fruit\tf\nveg\tv\n' ;;
+                1) printf '%s-1\n%s-2\n' "$1" "$RUNNER_INFO" ;;
+            esac
+        "#);
+
+        let top = CustomSource { config: source.clone() }.scan().unwrap();
+        assert_eq!(names(&top), ["fruit", "veg"]);
+        assert_eq!(top[1].command, "v");
+        assert_eq!(top[1].source, "menu");
+
+        let second = select(&source, &["veg".to_string()], &top[1].command).unwrap();
+        assert_eq!(names(&second), ["veg-1", "v-2"]);
+
+        let third = select(&source, &["veg".to_string(), "veg-1".to_string()], "").unwrap();
+        assert!(third.is_empty());
+    }
+
+    #[test]
+    fn failing_script_is_an_error() {
+        assert!(select(&script_source("exit 3"), &["x".to_string()], "").is_err());
+    }
+
+    #[test]
+    fn lines_without_a_name_are_skipped() {
+        let entries = run(&script_source(r#"printf 'a\tcmd\n\n\tno-name\nb\n'"#), &[], "").unwrap();
+        assert_eq!(names(&entries), ["a", "b"]);
+        assert_eq!(entries[0].command, "cmd");
+        assert_eq!(entries[1].command, "");
+    }
+}
@@ -1,127 +1,593 @@
+use crate::executor::shell_quote;
 use crate::model::{Entry, EntryType};
 use crate::sources::Source;
 use anyhow::Result;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use directories::BaseDirs;
-use log::{info, debug};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use log::{info, debug, warn};
 
-pub struct DesktopSource;
+pub struct DesktopSource {
+    /// Reuse parsed results from the on-disk cache for unchanged files
+    pub use_cache: bool,
+}
 
 impl Source for DesktopSource {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
     fn scan(&self) -> Result<Vec<Entry>> {
+        Ok(self.scan_dirs(application_dirs()))
+    }
+
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        application_dirs()
+    }
+}
+
+impl DesktopSource {
+    /// Entries from the `.desktop` files in `dirs`, highest precedence first.
+    fn scan_dirs(&self, dirs: Vec<PathBuf>) -> Vec<Entry> {
         let mut entries = Vec::new();
-        let mut data_dirs = Vec::new();
+        let mut old_cache = if self.use_cache { load_cache() } else { DesktopCache::default() };
+        let mut new_cache = DesktopCache { version: CACHE_VERSION, dirs: HashMap::new() };
+        // Desktop file ids already provided by a higher-precedence directory
+        let mut seen_ids = HashSet::new();
 
-        if let Some(base_dirs) = BaseDirs::new() {
-            data_dirs.push(base_dirs.data_dir().join("applications"));
-        }
-        data_dirs.push(Path::new("/usr/share/applications").to_path_buf());
-        data_dirs.push(Path::new("/usr/local/share/applications").to_path_buf());
-
-        for dir in data_dirs {
-            if dir.exists() {
-                debug!("Scanning desktop files in {:?}", dir);
-                if let Ok(read_dir) = fs::read_dir(dir) {
-                    for entry in read_dir.flatten() {
-                        let path = entry.path();
-                        if path.extension().and_then(|s| s.to_str()) == Some("desktop") {
-                            if let Ok(content) = fs::read_to_string(&path) {
-                                if let Some((name, exec, term, icon, container)) = parse_desktop_file(&content) {
-                                     let display_name = if let Some(c) = &container {
-                                         format!("{} ({})", name, c)
-                                     } else {
-                                         name
-                                     };
-                                     
-                                     let mut entry = Entry::new(
-                                         path.to_string_lossy().to_string(),
-                                         display_name,
-                                         exec,
-                                         EntryType::Desktop,
-                                         term,
-                                     );
-                                     entry.icon = icon;
-                                     entry.is_container = container.is_some();
-                                     entries.push(entry);
-                                }
-                            }
-                        }
-                    }
+        for dir in dirs {
+            if !dir.exists() { continue; }
+
+            let dir_mtime = mtime(&dir);
+            let old_dir = old_cache.dirs.remove(&dir);
+
+            // An unchanged directory mtime means no files were added or removed
+            let mut files: Vec<PathBuf> = match &old_dir {
+                Some(cached) if dir_mtime.is_some() && cached.mtime == dir_mtime => {
+                    debug!("Using cached file list for {:?}", dir);
+                    cached.files.keys().cloned().collect()
+                }
+                _ => {
+                    debug!("Scanning desktop files in {:?}", dir);
+                    fs::read_dir(&dir)
+                        .map(|rd| rd.flatten()
+                            .map(|e| e.path())
+                            .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("desktop"))
+                            .collect())
+                        .unwrap_or_default()
+                }
+            };
+            files.sort();
+
+            let mut dir_cache = DirCache { mtime: dir_mtime, files: HashMap::new() };
+            for path in files {
+                let Some(file_mtime) = mtime(&path) else { continue; };
+
+                let cached = old_dir.as_ref()
+                    .and_then(|d| d.files.get(&path))
+                    .filter(|f| f.mtime == file_mtime);
+                let parsed = match cached {
+                    Some(f) => f.parsed.clone(),
+                    None => fs::read_to_string(&path).ok().and_then(|c| parse_desktop_file(&c)),
+                };
+
+                // The first directory with an id wins, even when its copy is
+                // hidden (NoDisplay or Hidden), so a user override shadows the system
+                // file. Shadowed files stay cached for when the override goes.
+                let shadowed = path.file_name().is_some_and(|id| !seen_ids.insert(id.to_os_string()));
+                if shadowed {
+                    debug!("{:?} is overridden by an earlier directory", path);
+                } else if let Some(parsed) = &parsed {
+                    entries.extend(to_entries(&path, parsed.clone()));
                 }
+                dir_cache.files.insert(path, CachedFile { mtime: file_mtime, parsed });
             }
+            new_cache.dirs.insert(dir, dir_cache);
         }
+
+        if self.use_cache && let Err(e) = save_cache(&new_cache) {
+            warn!("Failed to write desktop cache: {}", e);
+        }
+
         info!("DesktopSource: found {} entries", entries.len());
-        Ok(entries)
+        entries
+    }
+}
+
+/// Directories holding `.desktop` files, highest precedence first
+fn application_dirs() -> Vec<PathBuf> {
+    super::xdg_data_dirs().into_iter().map(|dir| dir.join("applications")).collect()
+}
+
+/// Builds the application entry followed by one entry per desktop action.
+fn to_entries(path: &Path, parsed: DesktopFile) -> Vec<Entry> {
+    let id = path.to_string_lossy().to_string();
+    let display_name = if let Some(c) = &parsed.container {
+        format!("{} ({})", parsed.name, c)
+    } else {
+        parsed.name
+    };
+
+    let mut entries = Vec::with_capacity(1 + parsed.actions.len());
+    for action in parsed.actions {
+        // Action ids extend the parent's id so group filters on it apply to both
+        let mut entry = Entry::new(
+            format!("{}#{}", id, action.id),
+            format!("{} — {}", display_name, action.name),
+            action.exec,
+            EntryType::Desktop,
+            parsed.terminal,
+        );
+        entry.icon = action.icon.or_else(|| parsed.icon.clone());
+        entry.is_container = parsed.container.is_some();
+        entry.categories = parsed.categories.clone();
+        entry.wm_class = parsed.startup_wm_class.clone();
+        entries.push(entry);
+    }
+
+    let mut entry = Entry::new(
+        id,
+        display_name,
+        parsed.exec,
+        if parsed.link { EntryType::Link } else { EntryType::Desktop },
+        parsed.terminal,
+    );
+    entry.icon = parsed.icon;
+    entry.is_container = parsed.container.is_some();
+    entry.categories = parsed.categories;
+    entry.wm_class = parsed.startup_wm_class;
+    if parsed.dbus_activatable {
+        entry.dbus_name = dbus_name(path);
+    }
+    entries.insert(0, entry);
+    entries
+}
+
+/// The desktop file id doubles as the bus name of DBus activatable apps,
+/// e.g. `org.gnome.Nautilus.desktop`. Ids that aren't valid bus names
+/// (no dots) can't be activated and fall back to Exec.
+fn dbus_name(path: &Path) -> Option<String> {
+    let id = path.file_stem()?.to_str()?;
+    id.contains('.').then(|| id.to_string())
+}
+
+/// Bump whenever `DesktopFile` changes shape so stale caches are discarded.
+const CACHE_VERSION: u32 = 7;
+
+#[derive(Serialize, Deserialize, Default)]
+struct DesktopCache {
+    version: u32,
+    dirs: HashMap<PathBuf, DirCache>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DirCache {
+    mtime: Option<u64>,
+    files: HashMap<PathBuf, CachedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    mtime: u64,
+    parsed: Option<DesktopFile>,
+}
+
+fn get_cache_path() -> Option<PathBuf> {
+    ProjectDirs::from("org", "runner", "runner")
+        .map(|dirs| {
+            let data_dir = dirs.data_dir();
+            let _ = fs::create_dir_all(data_dir);
+            data_dir.join("desktop_cache.json")
+        })
+}
+
+fn load_cache() -> DesktopCache {
+    if let Some(path) = get_cache_path()
+        && let Ok(content) = fs::read_to_string(path)
+        && let Ok(cache) = serde_json::from_str::<DesktopCache>(&content)
+        && cache.version == CACHE_VERSION {
+        return cache;
     }
+    DesktopCache::default()
 }
 
-fn parse_desktop_file(content: &str) -> Option<(String, String, bool, Option<String>, Option<String>)> {
+fn save_cache(cache: &DesktopCache) -> Result<()> {
+    if let Some(path) = get_cache_path() {
+        fs::write(path, serde_json::to_string(cache)?)?;
+    }
+    Ok(())
+}
+
+/// Modification time in nanoseconds since the epoch
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DesktopFile {
+    name: String,
+    exec: String,
+    terminal: bool,
+    icon: Option<String>,
+    container: Option<String>,
+    actions: Vec<DesktopAction>,
+    dbus_activatable: bool,
+    categories: Vec<String>,
+    startup_wm_class: Option<String>,
+    /// `Type=Link`: `exec` opens its URL
+    link: bool,
+}
+
+/// A `[Desktop Action <id>]` group, e.g. Firefox's "New Private Window"
+#[derive(Serialize, Deserialize, Clone)]
+struct DesktopAction {
+    id: String,
+    name: String,
+    exec: String,
+    icon: Option<String>,
+}
+
+enum Section {
+    Entry,
+    /// The action being filled in is always the last one in `actions`
+    Action,
+    Other,
+}
+
+#[derive(Default)]
+struct ActionBuilder {
+    name: Option<String>,
+    exec: Option<String>,
+    icon: Option<String>,
+}
+
+/// An application or, for `Type=Link`, a bookmark opened with `xdg-open`.
+/// Menu directories and unknown types are skipped.
+fn parse_desktop_file(content: &str) -> Option<DesktopFile> {
+    let mut entry_type = None;
+    let mut url = None;
     let mut name = None;
     let mut exec = None;
     let mut terminal = false;
     let mut no_display = false;
+    let mut hidden = false;
+    let mut dbus_activatable = false;
     let mut icon = None;
-    let mut is_desktop_entry = false;
+    let mut categories = Vec::new();
+    let mut startup_wm_class = None;
+    let mut action_ids: Option<Vec<String>> = None;
+    let mut actions: Vec<(String, ActionBuilder)> = Vec::new();
+    let mut section = Section::Other;
 
     for line in content.lines() {
         let line = line.trim();
         if line.is_empty() || line.starts_with('#') { continue; }
 
-        if line == "[Desktop Entry]" {
-            is_desktop_entry = true;
-            continue;
-        }
-        
         if line.starts_with('[') {
-            is_desktop_entry = false; 
+            section = if line == "[Desktop Entry]" {
+                Section::Entry
+            } else if let Some(id) = line.strip_prefix("[Desktop Action ").and_then(|l| l.strip_suffix(']')) {
+                actions.push((id.to_string(), ActionBuilder::default()));
+                Section::Action
+            } else {
+                Section::Other
+            };
             continue;
         }
 
-        if !is_desktop_entry { continue; }
-
-        if line.starts_with("Name=") {
-            name = Some(line.trim_start_matches("Name=").to_string());
-        } else if line.starts_with("Exec=") {
-            let raw_exec = line.trim_start_matches("Exec=");
-            let clean_exec: String = raw_exec.split_whitespace()
-                .filter(|s| !s.starts_with('%'))
-                .collect::<Vec<_>>()
-                .join(" ");
-            exec = Some(clean_exec);
-        } else if line.starts_with("Terminal=") {
-            terminal = line.trim_start_matches("Terminal=") == "true";
-        } else if line.starts_with("NoDisplay=") {
-            no_display = line.trim_start_matches("NoDisplay=") == "true";
-        } else if line.starts_with("Icon=") {
-            icon = Some(line.trim_start_matches("Icon=").to_string());
+        let Some((key, value)) = line.split_once('=') else { continue; };
+        let (key, value) = (key.trim(), value.trim());
+
+        match &section {
+            Section::Entry => match key {
+                "Type" => entry_type = Some(value.to_string()),
+                "URL" => url = Some(unescape(value)),
+                "Name" => name = Some(value.to_string()),
+                "Exec" => exec = Some(clean_exec(value)),
+                "Terminal" => terminal = value == "true",
+                "NoDisplay" => no_display = value == "true",
+                // Per the spec the file counts as deleted
+                "Hidden" => hidden = value == "true",
+                "DBusActivatable" => dbus_activatable = value == "true",
+                "Icon" => icon = Some(value.to_string()),
+                "Actions" => action_ids = Some(split_list(value)),
+                "Categories" => categories = split_list(value),
+                "StartupWMClass" => startup_wm_class = Some(value.to_string()),
+                _ => {}
+            },
+            Section::Action => {
+                let Some((_, action)) = actions.last_mut() else { continue; };
+                match key {
+                    "Name" => action.name = Some(value.to_string()),
+                    "Exec" => action.exec = Some(clean_exec(value)),
+                    "Icon" => action.icon = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            Section::Other => {}
         }
     }
 
-    if no_display { return None; }
+    if no_display || hidden { return None; }
+
+    // Type is required, but files without one are common enough to accept
+    let link = match entry_type.as_deref() {
+        Some("Application") | None => false,
+        Some("Link") => true,
+        _ => return None,
+    };
+    if link {
+        exec = Some(format!("xdg-open {}", shell_quote(&url?)));
+    }
 
     let mut container = None;
     if let Some(cmd) = &exec {
         if cmd.contains("distrobox-enter") {
             // naive parse: -n <name> or -n <name>
             let parts: Vec<&str> = cmd.split_whitespace().collect();
-            if let Some(pos) = parts.iter().position(|&x| x == "-n" || x == "--name") {
-                if pos + 1 < parts.len() {
-                    container = Some(parts[pos+1].to_string());
-                }
+            if let Some(pos) = parts.iter().position(|&x| x == "-n" || x == "--name")
+                && pos + 1 < parts.len() {
+                container = Some(parts[pos+1].to_string());
             }
         } else if cmd.contains("toolbox run") {
              let parts: Vec<&str> = cmd.split_whitespace().collect();
-             if let Some(pos) = parts.iter().position(|&x| x == "-c" || x == "--container") {
-                if pos + 1 < parts.len() {
-                    container = Some(parts[pos+1].to_string());
-                }
+             if let Some(pos) = parts.iter().position(|&x| x == "-c" || x == "--container")
+                && pos + 1 < parts.len() {
+                container = Some(parts[pos+1].to_string());
             }
         }
     }
 
+    // Only actions listed in the Actions key are valid per the spec
+    let actions = actions.into_iter()
+        .filter(|(id, _)| action_ids.as_ref().is_some_and(|ids| ids.contains(id)))
+        .filter_map(|(id, a)| Some(DesktopAction { id, name: a.name?, exec: a.exec?, icon: a.icon }))
+        .collect();
+
     match (name, exec) {
-        (Some(n), Some(e)) => Some((n, e, terminal, icon, container)),
+        (Some(name), Some(exec)) => Some(DesktopFile { name, exec, terminal, icon, container, actions, dbus_activatable, categories, startup_wm_class, link }),
         _ => None,
     }
 }
+
+/// Items of a `;`-separated list value like `Network;WebBrowser;`. The
+/// trailing `;` is optional.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(';').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()
+}
+
+/// An Exec value as a command line for the executor: split into arguments
+/// by the spec's quoting rules, field codes (%f, %U, ...) dropped, and
+/// arguments that need it requoted.
+fn clean_exec(raw_exec: &str) -> String {
+    split_exec(&unescape(raw_exec)).iter()
+        .filter(|arg| !is_field_code(arg))
+        .map(|arg| shell_quote(&strip_field_codes(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Undo the escapes every desktop file string value may use
+/// (`\s`, `\n`, `\t`, `\r`, `\\`).
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                // Not a string escape; may be an Exec quoting escape
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Exec arguments: separated by spaces, or in double quotes where `"`,
+/// `` ` ``, `$` and `\` are escaped with a backslash.
+fn split_exec(exec: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_arg = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => arg.push(chars.next().unwrap_or('\\')),
+                        _ => arg.push(c),
+                    }
+                }
+            }
+            ' ' | '\t' | '\n' => {
+                if std::mem::take(&mut in_arg) {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            _ => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// A lone field code argument like `%U`, which expands to nothing here.
+fn is_field_code(arg: &str) -> bool {
+    arg.len() == 2 && arg.starts_with('%') && arg != "%%"
+}
+
+/// Drop field codes inside an argument (`--file=%f`) and turn `%%` into `%`.
+fn strip_field_codes(arg: &str) -> String {
+    let mut out = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+        } else if let Some('%') = chars.next() {
+            out.push('%');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runner-desktop-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_desktop(dir: &Path, id: &str, lines: &[&str]) {
+        fs::write(dir.join(id), format!("[Desktop Entry]\n{}\n", lines.join("\n"))).unwrap();
+    }
+
+    #[test]
+    fn earlier_directories_shadow_later_ones_by_desktop_id() {
+        let root = temp_dir("shadowing");
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        write_desktop(&user, "editor.desktop", &["Name=My Editor", "Exec=editor --mine"]);
+        write_desktop(&system, "editor.desktop", &["Name=Editor", "Exec=editor"]);
+        // A hidden user copy removes the system app without adding anything
+        write_desktop(&user, "ads.desktop", &["Name=Ads", "Exec=ads", "NoDisplay=true"]);
+        write_desktop(&system, "ads.desktop", &["Name=Ads", "Exec=ads"]);
+        write_desktop(&system, "mail.desktop", &["Name=Mail", "Exec=mail"]);
+
+        let source = DesktopSource { use_cache: false };
+        let mut found: Vec<(String, String)> = source.scan_dirs(vec![user.clone(), system.clone(), root.join("missing")])
+            .into_iter()
+            .map(|e| (e.name, e.command))
+            .collect();
+        found.sort();
+        assert_eq!(found, [
+            ("Mail".to_string(), "mail".to_string()),
+            ("My Editor".to_string(), "editor --mine".to_string()),
+        ]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    fn parse(lines: &[&str]) -> Option<DesktopFile> {
+        parse_desktop_file(&lines.join("\n"))
+    }
+
+    #[test]
+    fn categories_are_split_on_semicolons() {
+        let parsed = parse(&["[Desktop Entry]", "Name=IDE", "Exec=ide", "Categories=Development;IDE; Utility ;"]).unwrap();
+        assert_eq!(parsed.categories, ["Development", "IDE", "Utility"]);
+        assert!(parse(&["[Desktop Entry]", "Name=IDE", "Exec=ide"]).unwrap().categories.is_empty());
+    }
+
+    #[test]
+    fn startup_wm_class_is_kept_on_the_app_and_its_actions() {
+        let parsed = parse(&[
+            "[Desktop Entry]", "Name=Code", "Exec=code %F", "StartupWMClass=Code-OSS", "Actions=new;",
+            "[Desktop Action new]", "Name=New Window", "Exec=code --new-window",
+        ]).unwrap();
+        assert_eq!(parsed.startup_wm_class.as_deref(), Some("Code-OSS"));
+
+        let entries = to_entries(Path::new("/usr/share/applications/code.desktop"), parsed);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.wm_class.as_deref() == Some("Code-OSS")));
+    }
+
+    #[test]
+    fn hidden_files_are_skipped() {
+        assert!(parse(&["[Desktop Entry]", "Name=Gone", "Exec=gone", "Hidden=true"]).is_none());
+        assert!(parse(&["[Desktop Entry]", "Name=Kept", "Exec=kept", "Hidden=false"]).is_some());
+    }
+
+    #[test]
+    fn a_hidden_override_deletes_the_system_file() {
+        let root = temp_dir("hidden");
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        write_desktop(&user, "tracker.desktop", &["Name=Tracker", "Exec=tracker", "Hidden=true"]);
+        write_desktop(&system, "tracker.desktop", &["Name=Tracker", "Exec=tracker"]);
+
+        let source = DesktopSource { use_cache: false };
+        assert!(source.scan_dirs(vec![user, system]).is_empty());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn links_open_their_url() {
+        let parsed = parse(&["[Desktop Entry]", "Type=Link", "Name=Docs", r"URL=https://example.com/a\sb?q=1&x=2"]).unwrap();
+        assert!(parsed.link);
+        assert_eq!(parsed.exec, "xdg-open 'https://example.com/a b?q=1&x=2'");
+
+        let entries = to_entries(Path::new("/usr/share/applications/docs.desktop"), parsed);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry_type, EntryType::Link);
+
+        // A link needs somewhere to go
+        assert!(parse(&["[Desktop Entry]", "Type=Link", "Name=Docs"]).is_none());
+    }
+
+    #[test]
+    fn only_applications_and_links_are_listed() {
+        let parsed = parse(&["[Desktop Entry]", "Type=Application", "Name=App", "Exec=app"]).unwrap();
+        assert!(!parsed.link);
+        assert_eq!(parsed.exec, "app");
+        assert!(parse(&["[Desktop Entry]", "Name=App", "Exec=app"]).is_some());
+
+        assert!(parse(&["[Desktop Entry]", "Type=Directory", "Name=Games", "Exec=games"]).is_none());
+        assert!(parse(&["[Desktop Entry]", "Type=Service", "Name=Daemon", "Exec=daemon"]).is_none());
+    }
+
+    #[test]
+    fn clean_exec_keeps_quoted_arguments_together() {
+        assert_eq!(clean_exec(r#"app "a b" c"#), "app 'a b' c");
+        assert_eq!(clean_exec(r#""/opt/My App/run" --x"#), "'/opt/My App/run' --x");
+    }
+
+    #[test]
+    fn clean_exec_unescapes_backslashes() {
+        // `\\\\` in the file is one literal backslash once both escape levels are undone
+        assert_eq!(clean_exec(r#"echo "a\\\\b""#), r"echo 'a\b'");
+        assert_eq!(clean_exec(r#"echo "\\$HOME""#), "echo '$HOME'");
+    }
+
+    #[test]
+    fn clean_exec_turns_double_percent_into_percent() {
+        assert_eq!(clean_exec("printf 100%%"), "printf 100%");
+    }
+
+    #[test]
+    fn clean_exec_drops_field_codes() {
+        assert_eq!(clean_exec("firefox %u"), "firefox");
+        assert_eq!(clean_exec("app %F --new-window %i %c %k"), "app --new-window");
+        assert_eq!(clean_exec("app --file=%f"), "app --file=");
+    }
+}
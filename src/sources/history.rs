@@ -1,13 +1,138 @@
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use nix::fcntl::{Flock, FlockArg};
+
+/// How many launch timestamps are kept per entry for frecency
+const MAX_TIMESTAMPS: usize = 10;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Group that flat, pre-per-group history files are migrated into
+const DEFAULT_GROUP: &str = "default";
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct History {
-    pub usage_counts: HashMap<String, u32>,
+    /// Usage per launch group, keyed by group name and then entry id, so
+    /// each group's ranking stays independent
+    #[serde(default)]
+    pub groups: HashMap<String, HashMap<String, Usage>>,
+    /// Flat pre-per-group format, only read to migrate old history files
+    #[serde(default, skip_serializing)]
+    usage: HashMap<String, Usage>,
+    /// Pre-frecency format, only read to migrate old history files
+    #[serde(default, skip_serializing)]
+    usage_counts: HashMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Usage {
+    pub count: u32,
+    /// Most recent launches, oldest first, in seconds since the epoch
+    #[serde(default)]
+    pub timestamps: Vec<u64>,
+}
+
+impl Usage {
+    /// Launch count weighted by how recent the remembered launches are, so
+    /// something used daily this week outranks something used a lot last year.
+    pub fn frecency(&self, now: u64) -> i64 {
+        let avg_weight = if self.timestamps.is_empty() {
+            recency_weight(u64::MAX)
+        } else {
+            let total: u64 = self.timestamps.iter()
+                .map(|&t| recency_weight(now.saturating_sub(t)))
+                .sum();
+            total / self.timestamps.len() as u64
+        };
+        self.count as i64 * avg_weight as i64
+    }
+
+    /// When it was last launched, in seconds since the epoch.
+    pub fn last_used(&self) -> Option<u64> {
+        self.timestamps.last().copied()
+    }
+}
+
+fn recency_weight(age: u64) -> u64 {
+    match age {
+        a if a < 4 * DAY => 100,
+        a if a < 14 * DAY => 70,
+        a if a < 31 * DAY => 50,
+        a if a < 90 * DAY => 30,
+        _ => 10,
+    }
+}
+
+impl History {
+    pub fn frecency(&self, group: &str, id: &str, now: u64) -> i64 {
+        self.groups.get(group)
+            .and_then(|g| g.get(id))
+            .map(|u| u.frecency(now))
+            .unwrap_or(0)
+    }
+
+    /// How `id` has been used in `group`, if at all.
+    pub fn usage(&self, group: &str, id: &str) -> Option<&Usage> {
+        self.groups.get(group)?.get(id)
+    }
+
+    pub fn record(&mut self, group: &str, id: &str, now: u64) {
+        if !is_remembered(id) {
+            return;
+        }
+        let usage = self.groups.entry(group.to_string()).or_default()
+            .entry(id.to_string()).or_default();
+        usage.count += 1;
+        usage.timestamps.push(now);
+        if usage.timestamps.len() > MAX_TIMESTAMPS {
+            let excess = usage.timestamps.len() - MAX_TIMESTAMPS;
+            usage.timestamps.drain(..excess);
+        }
+    }
+
+    /// Evict the lowest-ranked entries of `group` until at most `max_entries`
+    /// remain. `keep` (the entry just launched) is never evicted.
+    pub fn trim(&mut self, group: &str, max_entries: usize, keep: &str, now: u64) {
+        let Some(usage) = self.groups.get_mut(group) else { return };
+        if usage.len() <= max_entries {
+            return;
+        }
+
+        let mut ranked: Vec<(String, i64)> = usage.iter()
+            .filter(|(id, _)| id.as_str() != keep)
+            .map(|(id, u)| (id.clone(), u.frecency(now)))
+            .collect();
+        ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+
+        let slots = max_entries.saturating_sub(usize::from(usage.contains_key(keep)));
+        for (id, _) in ranked.into_iter().skip(slots) {
+            usage.remove(&id);
+        }
+    }
+
+    fn migrate(&mut self) {
+        if self.usage.is_empty() && self.usage_counts.is_empty() {
+            return;
+        }
+
+        let default = self.groups.entry(DEFAULT_GROUP.to_string()).or_default();
+        for (id, usage) in self.usage.drain() {
+            default.entry(id).or_insert(usage);
+        }
+        for (id, count) in self.usage_counts.drain() {
+            default.entry(id).or_insert(Usage { count, timestamps: Vec::new() });
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
 pub fn get_history_path() -> Option<PathBuf> {
@@ -20,12 +145,11 @@ pub fn get_history_path() -> Option<PathBuf> {
 }
 
 pub fn load_history() -> History {
-    if let Some(path) = get_history_path() {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(history) = serde_json::from_str(&content) {
-                return history;
-            }
-        }
+    if let Some(path) = get_history_path()
+        && let Ok(content) = fs::read_to_string(path)
+        && let Ok(mut history) = serde_json::from_str::<History>(&content) {
+        history.migrate();
+        return history;
     }
     History::default()
 }
@@ -33,14 +157,180 @@ pub fn load_history() -> History {
 pub fn save_history(history: &History) -> Result<()> {
     if let Some(path) = get_history_path() {
         let content = serde_json::to_string_pretty(history)?;
-        fs::write(path, content)?;
+        write_atomic(&path, content.as_bytes())?;
     }
     Ok(())
 }
 
-pub fn increment_usage(id: &str) -> Result<()> {
+fn last_query_path() -> Option<PathBuf> {
+    get_history_path().map(|path| path.with_file_name("last_query"))
+}
+
+/// The query the launcher was last closed with, for `restore_query`.
+pub fn load_last_query() -> Option<String> {
+    let query = fs::read_to_string(last_query_path()?).ok()?;
+    (!query.is_empty()).then_some(query)
+}
+
+pub fn save_last_query(query: &str) -> Result<()> {
+    if let Some(path) = last_query_path() {
+        write_atomic(&path, query.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write to a temporary file next to `path` and rename it into place, so a
+/// crash mid-write never leaves a truncated file behind.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Exclusive lock serializing read-modify-write cycles between concurrently
+/// running instances. Released when dropped.
+fn lock_history() -> Result<Option<Flock<File>>> {
+    let Some(path) = get_history_path() else { return Ok(None) };
+    let file = File::create(path.with_extension("lock"))?;
+    let lock = Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, errno)| anyhow!("Failed to lock history: {}", errno))?;
+    Ok(Some(lock))
+}
+
+pub fn increment_usage(group: &str, id: &str, history_size: usize) -> Result<()> {
+    if !is_remembered(id) {
+        return Ok(());
+    }
+    // Re-read under the lock so launches from other instances aren't lost
+    let _lock = lock_history()?;
     let mut history = load_history();
-    let count = history.usage_counts.entry(id.to_string()).or_insert(0);
-    *count += 1;
+    let now = now();
+    history.record(group, id, now);
+    history.trim(group, history_size, id, now);
     save_history(&history)
-}
\ No newline at end of file
+}
+
+/// Whether launching `id` counts towards history. Computed results, like
+/// conversions, would each become an entry of their own.
+fn is_remembered(id: &str) -> bool {
+    !id.starts_with(crate::convert::ID_PREFIX)
+}
+
+/// Forget all launch history, and the query `restore_query` would fill in.
+/// Returns false if there was nothing to delete.
+pub fn clear() -> Result<bool> {
+    let Some(path) = get_history_path() else { return Ok(false) };
+    let _lock = lock_history()?;
+    let removed_history = remove_if_present(&path)?;
+    let removed_query = match last_query_path() {
+        Some(path) => remove_if_present(&path)?,
+        None => false,
+    };
+    Ok(removed_history || removed_query)
+}
+
+/// Delete `path`. Returns false if it didn't exist.
+fn remove_if_present(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000 * DAY;
+
+    fn used(history: &mut History, group: &str, id: &str, times: usize, age: u64) {
+        for _ in 0..times {
+            history.record(group, id, NOW - age);
+        }
+    }
+
+    #[test]
+    fn trim_evicts_the_lowest_ranked() {
+        let mut history = History::default();
+        used(&mut history, "default", "daily", 5, 0);
+        used(&mut history, "default", "old", 5, 200 * DAY);
+        used(&mut history, "default", "once", 1, 0);
+        used(&mut history, "default", "new", 1, 0);
+
+        history.trim("default", 2, "new", NOW);
+        let mut kept: Vec<&String> = history.groups["default"].keys().collect();
+        kept.sort();
+        assert_eq!(kept, ["daily", "new"]);
+    }
+
+    #[test]
+    fn trim_only_touches_its_group() {
+        let mut history = History::default();
+        used(&mut history, "default", "a", 1, 0);
+        used(&mut history, "default", "b", 2, 0);
+        used(&mut history, "work", "c", 1, 0);
+
+        history.trim("default", 1, "", NOW);
+        assert_eq!(history.groups["default"].keys().collect::<Vec<_>>(), ["b"]);
+        assert_eq!(history.groups["work"].len(), 1);
+    }
+
+    #[test]
+    fn record_keeps_the_latest_timestamps() {
+        let mut history = History::default();
+        for i in 0..MAX_TIMESTAMPS as u64 + 5 {
+            history.record("default", "a", i);
+        }
+        let usage = history.usage("default", "a").unwrap();
+        assert_eq!(usage.count as usize, MAX_TIMESTAMPS + 5);
+        assert_eq!(usage.timestamps.len(), MAX_TIMESTAMPS);
+        assert_eq!(usage.last_used(), Some(MAX_TIMESTAMPS as u64 + 4));
+    }
+
+    #[test]
+    fn conversions_are_not_recorded() {
+        let mut history = History::default();
+        history.record("default", "convert:10 km to mi", NOW);
+        assert!(history.groups.is_empty());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runner-history-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_file_and_leaves_nothing_behind() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("history.json");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // A failed rename (onto a directory) keeps the old content and
+        // cleans up the temporary file
+        let blocked = dir.join("blocked");
+        fs::create_dir(&blocked).unwrap();
+        fs::write(blocked.join("inside"), "").unwrap();
+        assert!(write_atomic(&blocked, b"lost").is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,11 +1,101 @@
+use crate::config::Config;
 use crate::model::Entry;
 use anyhow::Result;
+use std::path::PathBuf;
 
 pub trait Source {
+    /// The name groups list it under, e.g. "desktop"
+    fn name(&self) -> &str;
+
     fn scan(&self) -> Result<Vec<Entry>>;
+
+    /// Directories whose changes should trigger a rescan
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/// `$XDG_DATA_HOME` followed by each of `$XDG_DATA_DIRS` (or the spec's
+/// default, /usr/local/share:/usr/share), in precedence order and without
+/// duplicates.
+pub fn xdg_data_dirs() -> Vec<PathBuf> {
+    let data_home = directories::BaseDirs::new().map(|base| base.data_dir().to_path_buf());
+    data_dirs(data_home, std::env::var("XDG_DATA_DIRS").ok().as_deref())
+}
+
+fn data_dirs(data_home: Option<PathBuf>, xdg_data_dirs: Option<&str>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = data_home.into_iter().collect();
+    let data_dirs = xdg_data_dirs
+        .filter(|d| !d.is_empty())
+        .unwrap_or("/usr/local/share:/usr/share");
+    for dir in data_dirs.split(':').filter(|d| !d.is_empty()) {
+        // Trailing slashes don't make a different directory
+        let dir = PathBuf::from(dir.trim_end_matches('/'));
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Scan `source`, tagging every entry with the source's name.
+pub fn scan(source: &dyn Source) -> Result<Vec<Entry>> {
+    let mut entries = source.scan()?;
+    for entry in &mut entries {
+        entry.source = source.name().to_string();
+    }
+    Ok(entries)
+}
+
+/// The scanner for a source name from a group's `sources`, built-in or a
+/// `[[custom_source]]`, or `None` for names without one (like "history",
+/// which only affects ranking).
+pub fn by_name(name: &str, config: &Config) -> Option<Box<dyn Source + Send>> {
+    match name {
+        "desktop" => Some(Box::new(desktop::DesktopSource { use_cache: config.general.cache_desktop })),
+        "bin" => Some(Box::new(bin::BinSource)),
+        "scripts" => Some(Box::new(scripts::ScriptsSource { config: config.scripts.clone() })),
+        "recent" => Some(Box::new(recent::RecentSource)),
+        "power" => Some(Box::new(power::PowerSource { config: config.power.clone() })),
+        _ => config.custom_source.iter()
+            .find(|custom| custom.name == name)
+            .map(|custom| Box::new(custom::CustomSource { config: custom.clone() }) as Box<dyn Source + Send>),
+    }
 }
 
 pub mod desktop;
 pub mod bin;
 pub mod history;
 pub mod scripts;
+pub mod recent;
+pub mod power;
+pub mod custom;
+pub mod stdin;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(dirs: &[&str]) -> Vec<PathBuf> {
+        dirs.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn data_home_comes_first() {
+        let dirs = data_dirs(Some("/home/u/.local/share".into()), Some("/opt/share:/usr/share"));
+        assert_eq!(dirs, paths(&["/home/u/.local/share", "/opt/share", "/usr/share"]));
+    }
+
+    #[test]
+    fn unset_or_empty_data_dirs_use_the_default() {
+        for unset in [None, Some("")] {
+            assert_eq!(data_dirs(None, unset), paths(&["/usr/local/share", "/usr/share"]));
+        }
+    }
+
+    #[test]
+    fn duplicate_and_empty_data_dirs_are_dropped() {
+        let dirs = data_dirs(Some("/usr/share".into()), Some("::/usr/share/:/var/lib/flatpak/exports/share:/usr/share"));
+        assert_eq!(dirs, paths(&["/usr/share", "/var/lib/flatpak/exports/share"]));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::config::PowerConfig;
+use crate::model::{Entry, EntryType};
+use crate::sources::Source;
+use anyhow::Result;
+use log::info;
+
+/// Session actions (lock, log out, suspend, reboot, power off) with the
+/// commands from the `[power]` config section.
+pub struct PowerSource {
+    pub config: PowerConfig,
+}
+
+impl Source for PowerSource {
+    fn name(&self) -> &str {
+        "power"
+    }
+
+    fn scan(&self) -> Result<Vec<Entry>> {
+        let actions = [
+            ("lock", "Lock", "system-lock-screen", &self.config.lock),
+            ("logout", "Log Out", "system-log-out", &self.config.logout),
+            ("suspend", "Suspend", "system-suspend", &self.config.suspend),
+            ("reboot", "Reboot", "system-reboot", &self.config.reboot),
+            ("poweroff", "Power Off", "system-shutdown", &self.config.poweroff),
+        ];
+
+        // An empty command hides the action
+        let entries: Vec<Entry> = actions.into_iter()
+            .filter(|(_, _, _, command)| !command.trim().is_empty())
+            .map(|(id, name, icon, command)| {
+                let mut entry = Entry::new(format!("power:{}", id), name.to_string(), command.clone(), EntryType::Custom, false);
+                entry.icon = Some(icon.to_string());
+                entry
+            })
+            .collect();
+
+        info!("PowerSource: found {} entries", entries.len());
+        Ok(entries)
+    }
+}
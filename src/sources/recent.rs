@@ -0,0 +1,191 @@
+use crate::model::{Entry, EntryType};
+use crate::executor::shell_quote;
+use crate::sources::Source;
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use log::{info, debug};
+use directories::BaseDirs;
+
+/// Files from the freedesktop recently-used list that GTK and most other
+/// toolkits maintain, opened with `xdg-open`.
+pub struct RecentSource;
+
+impl Source for RecentSource {
+    fn name(&self) -> &str {
+        "recent"
+    }
+
+    fn scan(&self) -> Result<Vec<Entry>> {
+        let Some(dirs) = BaseDirs::new() else {
+            return Ok(vec![]);
+        };
+        let xbel_path = dirs.data_dir().join("recently-used.xbel");
+
+        let content = match fs::read_to_string(&xbel_path) {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("Can't read {:?}, skipping: {}", xbel_path, e);
+                return Ok(vec![]);
+            }
+        };
+        let doc = match roxmltree::Document::parse(&content) {
+            Ok(doc) => doc,
+            Err(e) => {
+                debug!("Malformed {:?}, skipping: {}", xbel_path, e);
+                return Ok(vec![]);
+            }
+        };
+
+        let mut entries = Vec::new();
+        for bookmark in doc.descendants().filter(|n| n.has_tag_name("bookmark")) {
+            let Some(href) = bookmark.attribute("href") else { continue };
+            // Only local files that still exist; the list keeps deleted ones around
+            let Some(path) = href.strip_prefix("file://").map(percent_decode) else { continue };
+            let path = Path::new(&path);
+            if !path.exists() {
+                continue;
+            }
+            let Some(file_name) = path.file_name() else { continue };
+
+            let mut entry = Entry::new(
+                href.to_string(),
+                file_name.to_string_lossy().to_string(),
+                format!("xdg-open {}", shell_quote(href)),
+                EntryType::File,
+                false,
+            );
+            entry.description = Some(path.to_string_lossy().to_string());
+            entry.modified = bookmark.attribute("modified").and_then(parse_timestamp);
+            // Theme icons are named after the MIME type, e.g. "text-plain"
+            entry.icon = bookmark.descendants()
+                .find(|n| n.has_tag_name("mime-type"))
+                .and_then(|n| n.attribute("type"))
+                .map(|mime| mime.replace('/', "-"));
+            entries.push(entry);
+        }
+
+        entries.sort_by_key(|e| std::cmp::Reverse(e.modified));
+        info!("RecentSource: found {} entries", entries.len());
+        Ok(entries)
+    }
+}
+
+/// Decode `%XX` escapes in a file URI path. Invalid escapes are kept as is.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| input.get(i + 1..i + 3))
+            .flatten()
+            // from_str_radix would also take a sign, as in "%+1"
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Seconds since the epoch for an xbel timestamp like
+/// "2024-01-15T10:30:00.123456Z". Fractions and offsets are ignored; xbel
+/// files are written in UTC. `None` for anything that isn't a valid date.
+fn parse_timestamp(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(number);
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.get(..8)?.splitn(3, ':').map(number);
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    // 60 is a leap second
+    if !(1..=month_days).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Days from civil date, Howard Hinnant's algorithm
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// A timestamp field: digits only, no sign
+fn number(field: &str) -> Option<i64> {
+    if field.is_empty() || !field.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    field.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_spaces_and_utf8() {
+        assert_eq!(percent_decode("/home/me/My%20Files/a%2Bb.txt"), "/home/me/My Files/a+b.txt");
+        assert_eq!(percent_decode("/tmp/caf%C3%A9/%E6%97%A5%E6%9C%AC"), "/tmp/café/日本");
+        assert_eq!(percent_decode("/plain/ünïcode"), "/plain/ünïcode");
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz%+1%-1"), "%zz%+1%-1");
+        assert_eq!(percent_decode("%%41"), "%A");
+        // A lone byte of a multibyte sequence can't be kept as UTF-8
+        assert_eq!(percent_decode("a%C3b"), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn timestamps_in_utc() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00Z"), Some(1_705_314_600));
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00.123456Z"), Some(1_705_314_600));
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00+02:00"), Some(1_705_314_600));
+    }
+
+    #[test]
+    fn timestamps_across_leap_days() {
+        assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+        assert_eq!(parse_timestamp("2024-03-01T00:00:00Z"), Some(1_709_164_800 + 86400));
+        assert_eq!(parse_timestamp("2000-02-29T12:00:00Z"), Some(951_825_600));
+        assert_eq!(parse_timestamp("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("1900-02-29T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        for value in [
+            "", "2024-01-15", "2024-01-15T", "2024-01-15T10:30", "2024-01-15 10:30:00Z",
+            "2024-13-01T00:00:00Z", "2024-00-10T00:00:00Z", "2024-04-31T00:00:00Z",
+            "2024-01-15T24:00:00Z", "2024-01-15T10:60:00Z", "2024-01-15T1a:30:00Z",
+            "2024-+1-15T10:30:00Z", "1969-12-31T23:59:59Z", "yesterday",
+        ] {
+            assert_eq!(parse_timestamp(value), None, "{:?}", value);
+        }
+    }
+}
@@ -1,54 +1,253 @@
+use crate::config::ScriptsConfig;
+use crate::executor::shell_quote;
 use crate::model::{Entry, EntryType};
 use crate::sources::Source;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::os::unix::fs::PermissionsExt;
 use log::{info, debug};
 use directories::ProjectDirs;
 
-pub struct ScriptsSource;
+/// Executable files in the `scripts` config directory and the `[scripts]`
+/// `dirs`. Comments near the top
+/// of a script can set how it's listed:
+///
+/// ```sh
+/// # name: Backup Home
+/// # description: rsync ~ to the NAS
+/// # terminal: true
+/// # icon: drive-harddisk
+/// ```
+///
+/// A script `foo` without an icon comment gets `foo.png` or `foo.svg` next
+/// to it or in `icons/`.
+pub struct ScriptsSource {
+    pub config: ScriptsConfig,
+}
+
+/// How many lines at the top of a script are checked for metadata comments
+const HEADER_LINES: usize = 10;
 
 impl Source for ScriptsSource {
+    fn name(&self) -> &str {
+        "scripts"
+    }
+
     fn scan(&self) -> Result<Vec<Entry>> {
         let mut entries = Vec::new();
-        
-        let proj_dirs = ProjectDirs::from("org", "runner", "runner");
-        let scripts_dir = if let Some(dirs) = proj_dirs {
-            dirs.config_dir().join("scripts")
-        } else {
-            return Ok(vec![]);
-        };
-
-        if !scripts_dir.exists() {
-            debug!("Scripts directory {:?} does not exist, skipping", scripts_dir);
-            return Ok(vec![]);
-        }
 
-        debug!("Scanning scripts in {:?}", scripts_dir);
-        if let Ok(read_dir) = fs::read_dir(scripts_dir) {
-            for entry in read_dir.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Ok(metadata) = fs::metadata(&path) {
-                        // Check if executable
-                        if metadata.permissions().mode() & 0o111 != 0 {
-                             if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
-                                 // Scripts usually don't have icons, but we could look for name.png
-                                 entries.push(Entry::new(
-                                     path.to_string_lossy().to_string(),
-                                     file_name.to_string(),
-                                     path.to_string_lossy().to_string(),
-                                     EntryType::Custom,
-                                     false, // Assume scripts manage their own terminal state or don't need one
-                                 ));
-                             }
-                        }
-                    }
-                }
+        for scripts_dir in self.dirs() {
+            if !scripts_dir.exists() {
+                debug!("Scripts directory {:?} does not exist, skipping", scripts_dir);
+                continue;
             }
+            debug!("Scanning scripts in {:?}", scripts_dir);
+            scan_dir(&scripts_dir, self.config.include_non_executable, &mut entries);
         }
-        
+
         info!("ScriptsSource: found {} entries", entries.len());
         Ok(entries)
     }
+
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        self.dirs()
+    }
+}
+
+impl ScriptsSource {
+    /// The default directory followed by the configured ones, each once
+    fn dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut seen = HashSet::new();
+        let configured = self.config.dirs.iter()
+            .map(|dir| PathBuf::from(crate::executor::expand_for_group(dir, None)));
+        for dir in scripts_dir().into_iter().chain(configured) {
+            // Symlinked or differently spelled paths to the same directory
+            // would list its scripts twice
+            let resolved = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+            if seen.insert(resolved) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+}
+
+/// Interpreters for scripts without the executable bit that have no
+/// shebang, by extension. Only files with one of these are listed.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("sh", "sh"),
+    ("bash", "bash"),
+    ("zsh", "zsh"),
+    ("fish", "fish"),
+    ("py", "python3"),
+    ("pl", "perl"),
+    ("rb", "ruby"),
+];
+
+fn scan_dir(scripts_dir: &Path, include_non_executable: bool, entries: &mut Vec<Entry>) {
+    if let Ok(read_dir) = fs::read_dir(scripts_dir) {
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            if !path.is_file() { continue; }
+            let Ok(metadata) = fs::metadata(&path) else { continue };
+            let Some(file_name) = path.file_name().and_then(|s| s.to_str()) else { continue };
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            let interpreter = path.extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| INTERPRETERS.iter().find(|(known, _)| *known == ext))
+                .map(|(_, interpreter)| *interpreter);
+            let listed = executable || (include_non_executable && interpreter.is_some());
+            if !listed { continue; }
+
+            let header = read_header(&path);
+            let path_str = path.to_string_lossy().to_string();
+            // Without the executable bit the kernel won't read the shebang,
+            // so do what it would have done
+            let quoted = shell_quote(&path_str);
+            let command = match (executable, &header.shebang, interpreter) {
+                (true, _, _) => quoted,
+                (false, Some(shebang), _) => format!("{} {}", shebang, quoted),
+                (false, None, Some(interpreter)) => format!("{} {}", interpreter, quoted),
+                (false, None, None) => continue,
+            };
+            let mut script = Entry::new(
+                path_str,
+                header.name.unwrap_or_else(|| file_name.to_string()),
+                command,
+                EntryType::Custom,
+                header.terminal,
+            );
+            script.description = header.description;
+            script.icon = header.icon.or_else(|| sidecar_icon(scripts_dir, &path));
+            entries.push(script);
+        }
+    }
+}
+
+fn scripts_dir() -> Option<PathBuf> {
+    ProjectDirs::from("org", "runner", "runner").map(|dirs| dirs.config_dir().join("scripts"))
+}
+
+/// `<stem>.png` or `<stem>.svg` beside the script or in `icons/`.
+fn sidecar_icon(scripts_dir: &Path, script: &Path) -> Option<String> {
+    let stem = script.file_stem()?.to_str()?;
+    [scripts_dir.to_path_buf(), scripts_dir.join("icons")].iter()
+        .flat_map(|dir| ["png", "svg"].map(|ext| dir.join(format!("{}.{}", stem, ext))))
+        .find(|candidate| candidate.is_file())
+        .map(|candidate| candidate.to_string_lossy().to_string())
+}
+
+/// What a script's leading comments say about it
+#[derive(Default)]
+struct ScriptHeader {
+    name: Option<String>,
+    description: Option<String>,
+    icon: Option<String>,
+    terminal: bool,
+    /// The interpreter and arguments from a `#!` first line
+    shebang: Option<String>,
+}
+
+fn read_header(script: &Path) -> ScriptHeader {
+    match fs::File::open(script) {
+        Ok(file) => parse_header(BufReader::new(file)),
+        Err(_) => ScriptHeader::default(),
+    }
+}
+
+/// `# key: value` lines among the first few of the script. Works with `//`
+/// and `--` comments too; other lines and unknown keys are ignored.
+fn parse_header(reader: impl BufRead) -> ScriptHeader {
+    let mut header = ScriptHeader::default();
+    for (line_no, line) in reader.split(b'\n').take(HEADER_LINES).map_while(Result::ok).enumerate() {
+        let line = String::from_utf8_lossy(&line);
+        if line_no == 0
+            && let Some(shebang) = line.strip_prefix("#!") {
+            let shebang = shebang.trim();
+            header.shebang = (!shebang.is_empty()).then(|| shebang.to_string());
+            continue;
+        }
+        // Code such as `url: str = ...` in a Python script isn't metadata
+        let trimmed = line.trim_start();
+        let Some(comment) = ["#", "//", "--"].iter().find_map(|prefix| trimmed.strip_prefix(prefix)) else { continue };
+        let comment = comment.trim_start_matches(['#', '/', '-']).trim_start();
+        let Some((key, value)) = comment.split_once(':') else { continue };
+        let value = value.trim();
+        if value.is_empty() { continue; }
+        match key.trim().to_lowercase().as_str() {
+            "name" => header.name = Some(value.to_string()),
+            "description" => header.description = Some(value.to_string()),
+            "icon" => header.icon = Some(value.to_string()),
+            "terminal" => header.terminal = value == "true",
+            _ => {}
+        }
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_reads_comment_metadata() {
+        let script = "#!/bin/bash -e\n# name: Backup Home\n# Description: rsync ~ to the NAS\n# terminal: true\n# icon: drive-harddisk\n";
+        let header = parse_header(script.as_bytes());
+        assert_eq!(header.shebang.as_deref(), Some("/bin/bash -e"));
+        assert_eq!(header.name.as_deref(), Some("Backup Home"));
+        assert_eq!(header.description.as_deref(), Some("rsync ~ to the NAS"));
+        assert_eq!(header.icon.as_deref(), Some("drive-harddisk"));
+        assert!(header.terminal);
+    }
+
+    #[test]
+    fn header_accepts_slash_and_dash_comments() {
+        assert_eq!(parse_header("// name: From JS\n".as_bytes()).name.as_deref(), Some("From JS"));
+        assert_eq!(parse_header("-- name: From Lua\n".as_bytes()).name.as_deref(), Some("From Lua"));
+        assert_eq!(parse_header("## name: Doubled\n".as_bytes()).name.as_deref(), Some("Doubled"));
+    }
+
+    #[test]
+    fn header_ignores_code_lines() {
+        let script = "#!/usr/bin/env python3\nname: str = 'not metadata'\nicon = 'x'\nterminal: true\n";
+        let header = parse_header(script.as_bytes());
+        assert_eq!(header.shebang.as_deref(), Some("/usr/bin/env python3"));
+        assert!(header.name.is_none());
+        assert!(header.icon.is_none());
+        assert!(!header.terminal);
+    }
+
+    #[test]
+    fn header_only_reads_the_first_lines() {
+        let script = format!("{}# name: Too Late\n", "echo\n".repeat(HEADER_LINES));
+        assert!(parse_header(script.as_bytes()).name.is_none());
+    }
+
+    #[test]
+    fn sidecar_icons_beside_the_script_or_in_icons() {
+        let dir = std::env::temp_dir().join(format!("runner-scripts-{}-sidecar", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("icons")).unwrap();
+        for file in ["backup.sh", "backup.svg", "deploy", "icons/deploy.png", "icons/deploy.svg", "plain"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let icon = |script: &str| sidecar_icon(&dir, &dir.join(script));
+        assert_eq!(icon("backup.sh"), Some(dir.join("backup.svg").to_string_lossy().to_string()));
+        // png is preferred over svg
+        assert_eq!(icon("deploy"), Some(dir.join("icons/deploy.png").to_string_lossy().to_string()));
+        assert_eq!(icon("plain"), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn shebang_only_counts_on_the_first_line() {
+        let header = parse_header("# name: x\n#!/bin/sh\n".as_bytes());
+        assert!(header.shebang.is_none());
+    }
 }
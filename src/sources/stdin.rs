@@ -0,0 +1,36 @@
+use crate::model::{Entry, EntryType};
+use std::io::{self, BufRead};
+use log::{info, warn};
+
+/// Lines are handed over in batches so the first ones show up while a large
+/// input is still being read.
+const BATCH_SIZE: usize = 5000;
+
+/// Read newline-separated choices from stdin for `--dmenu`, sending them to
+/// `tx` in input order until EOF.
+pub fn stream_lines(tx: calloop::channel::Sender<Vec<Entry>>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut count = 0;
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Stopped reading stdin: {}", e);
+                break;
+            }
+        };
+        if line.is_empty() { continue; }
+
+        batch.push(Entry::new(format!("dmenu:{}", count), line, String::new(), EntryType::Line, false));
+        count += 1;
+        if batch.len() == BATCH_SIZE && tx.send(std::mem::take(&mut batch)).is_err() {
+            return;
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = tx.send(batch);
+    }
+    info!("StdinSource: read {} lines", count);
+}
@@ -1,263 +1,1053 @@
-use crate::model::Entry;
-use crate::config::Config;
-use crate::matcher::FuzzyMatcher;
+use crate::model::{Entry, EntryType};
+use crate::config::{Config, DefaultSort, Grouping, LaunchGroup, MatchOn, TabCompletion};
+use crate::convert;
+use crate::matcher::{FuzzyMatcher, MatchResult, MatchWorker};
 use crate::sources::history::{self, History};
+use crate::ui::render::{item_height, list_bottom, list_start_y};
 use regex::Regex;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// `filtered_indices` at or above this refer to `query_entries` rather than
+/// `entries`, so they stay valid while more entries are loaded.
+const QUERY_ENTRY_BASE: usize = usize::MAX / 2;
+
+/// A line of the result list: a section header in `grouped` mode, or the
+/// entry at a position in `filtered_indices`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Row {
+    Header(usize),
+    Entry(usize),
+}
 
 pub struct AppState {
-
     pub config: Config,
-
-    pub entries: Vec<Entry>,
-
+    pub entries: Arc<Vec<Entry>>,
     pub filtered_indices: Vec<usize>,
-
+    /// Entries computed from the query itself, such as unit conversions,
+    /// listed above the matches
+    pub query_entries: Vec<Entry>,
     pub selected_index: usize,
-
+    /// Position in `filtered_indices` of the row under the mouse pointer,
+    /// when hovering doesn't move the selection
+    pub hover_index: Option<usize>,
     pub query: String,
-
+    /// The whole query is selected, so typing replaces it; set when it was
+    /// filled in rather than typed
+    pub query_selected: bool,
     pub matcher: FuzzyMatcher,
-
+    pub match_worker: MatchWorker,
+    /// Bumped on every filter request; results for older generations are stale.
+    filter_generation: u64,
+    filter_pending: bool,
     pub active_group: String,
-
     pub history: History,
-
+    /// Picking from stdin lines: keep their order and ignore group filters
+    pub dmenu: bool,
+    /// `--multi`: Tab marks entries and Enter acts on all marked ones
+    pub multi: bool,
+    /// Indices into `entries` of the marked entries
+    pub marked: HashSet<usize>,
+    /// The active group's compiled filters, and the group they belong to
+    group_filter: Option<(String, GroupFilter)>,
+    /// Why the last launch failed, shown until the next key press
+    pub error: Option<String>,
+    /// The query the current list was filtered for
+    filtered_query: Option<String>,
+    /// Id of the entry to keep selected through the next re-filter, if it
+    /// is for the same query
+    keep_selected: Option<String>,
+    /// In `grouped` mode, the position in `filtered_indices` where each
+    /// section starts and its header
+    sections: Vec<(usize, String)>,
+    /// Open levels of a script source's menu, innermost last. While any are
+    /// open `entries` holds the innermost level's lines.
+    script_levels: Vec<ScriptLevel>,
 }
 
-
+/// A menu level opened by picking an entry of a script source.
+struct ScriptLevel {
+    /// Names picked to get here, outermost first
+    selections: Vec<String>,
+    /// The list and query of the level below, restored when going back
+    below: Arc<Vec<Entry>>,
+    below_query: String,
+}
 
 impl AppState {
-
-    pub fn new(config: Config) -> Self {
-
+    pub fn new(config: Config, match_worker: MatchWorker) -> Self {
         Self {
-
             config,
-
-            entries: Vec::new(),
-
+            entries: Arc::new(Vec::new()),
             filtered_indices: Vec::new(),
-
+            query_entries: Vec::new(),
             selected_index: 0,
-
+            hover_index: None,
             query: String::new(),
-
+            query_selected: false,
             matcher: FuzzyMatcher::new(),
-
+            match_worker,
+            filter_generation: 0,
+            filter_pending: false,
             active_group: "default".to_string(),
-
             history: history::load_history(),
+            dmenu: false,
+            multi: false,
+            marked: HashSet::new(),
+            group_filter: None,
+            error: None,
+            filtered_query: None,
+            keep_selected: None,
+            sections: Vec::new(),
+            script_levels: Vec::new(),
+        }
+    }
 
+    /// Start over with an empty query and freshly loaded history, for when
+    /// the daemon shows the launcher again.
+    pub fn reset(&mut self) {
+        if let Some(bottom) = self.script_levels.drain(..).next() {
+            self.entries = bottom.below;
         }
+        self.query.clear();
+        self.query_selected = false;
+        self.restore_query();
+        self.error = None;
+        self.marked.clear();
+        self.history = history::load_history();
+        self.filter_now();
+    }
 
+    /// With `restore_query`, fill in the query the launcher was last closed
+    /// with, selected. Picking from stdin always starts empty.
+    pub fn restore_query(&mut self) {
+        if !self.config.general.restore_query || self.dmenu {
+            return;
+        }
+        if let Some(query) = history::load_last_query() {
+            self.prefill_query(&query);
+        }
     }
 
+    /// Start with `query` typed in and selected. An empty one changes nothing.
+    pub fn prefill_query(&mut self, query: &str) {
+        if !query.is_empty() {
+            self.query = query.to_string();
+            self.query_selected = true;
+        }
+    }
 
+    /// With `restore_query`, remember the query for the next time the
+    /// launcher opens. Inside a script menu that's the query it was opened
+    /// from.
+    pub fn save_query(&self) {
+        if !self.config.general.restore_query || self.dmenu {
+            return;
+        }
+        let query = self.script_levels.first().map_or(&self.query, |level| &level.below_query);
+        if let Err(e) = history::save_last_query(query) {
+            log::warn!("Failed to save the query: {}", e);
+        }
+    }
 
-    pub fn set_entries(&mut self, entries: Vec<Entry>) {
+    /// Typing into a selected query replaces it.
+    pub fn edit_query(&mut self) {
+        if std::mem::take(&mut self.query_selected) {
+            self.query.clear();
+        }
+    }
 
-        self.entries = entries;
+    /// The entry a `filtered_indices` value refers to.
+    pub fn entry(&self, idx: usize) -> &Entry {
+        match idx.checked_sub(QUERY_ENTRY_BASE) {
+            Some(i) => &self.query_entries[i],
+            None => &self.entries[idx],
+        }
+    }
 
+    /// Swap in a reloaded config and re-filter with it.
+    pub fn set_config(&mut self, config: Config) {
+        self.remember_selection();
+        self.config = config;
+        self.group_filter = None;
         self.update_filter();
-
     }
 
+    /// Append a batch of newly scanned entries and re-filter.
+    pub fn add_entries(&mut self, entries: Vec<Entry>) {
+        if self.script_levels.is_empty() {
+            self.remember_selection();
+        }
+        let dedupe = self.config.general.dedupe_bin_desktop;
+        let all = Arc::make_mut(self.scanned_entries());
+        let appended_len = all.len() + entries.len();
+        all.extend(entries);
+        if dedupe {
+            dedupe_bin_desktop(all);
+        }
+        // Marks are indices, which only survive appending
+        if all.len() != appended_len {
+            self.marked.clear();
+        }
+        if self.script_levels.is_empty() {
+            self.update_filter();
+        }
+    }
 
+    /// Swap everything `source` produced for a fresh scan of it. Filters
+    /// synchronously, as indices into the old list are no longer valid.
+    pub fn replace_source(&mut self, source: &str, entries: Vec<Entry>) {
+        if self.script_levels.is_empty() {
+            self.remember_selection();
+        }
+        let dedupe = self.config.general.dedupe_bin_desktop;
+        let all = Arc::make_mut(self.scanned_entries());
+        all.retain(|e| e.source != source);
+        all.extend(entries);
+        if dedupe {
+            dedupe_bin_desktop(all);
+        }
+        self.marked.clear();
+        if self.script_levels.is_empty() {
+            self.filter_now();
+        }
+    }
 
-    pub fn update_query(&mut self, query: &str) {
+    /// Keep the selected entry selected through the next re-filter, unless
+    /// the query changes first. Entries arriving or a source being rescanned
+    /// shouldn't move the selection away from under the user.
+    fn remember_selection(&mut self) {
+        self.keep_selected = self.filtered_indices.get(self.selected_index)
+            .map(|&idx| self.entry(idx).id.clone());
+    }
 
-        self.query = query.to_string();
+    /// The scanned entries, wherever they are kept while a script menu is open.
+    fn scanned_entries(&mut self) -> &mut Arc<Vec<Entry>> {
+        match self.script_levels.first_mut() {
+            Some(bottom) => &mut bottom.below,
+            None => &mut self.entries,
+        }
+    }
 
-        self.update_filter();
+    /// Names picked in the open script menu so far, outermost first.
+    pub fn script_selections(&self) -> &[String] {
+        self.script_levels.last().map_or(&[], |level| &level.selections)
+    }
 
+    /// Open a script menu level listing `entries`, reached by picking
+    /// `selections`. The query starts out empty.
+    pub fn push_script_level(&mut self, selections: Vec<String>, entries: Vec<Entry>) {
+        let below = std::mem::replace(&mut self.entries, Arc::new(entries));
+        let below_query = std::mem::take(&mut self.query);
+        self.query_selected = false;
+        self.script_levels.push(ScriptLevel { selections, below, below_query });
+        self.marked.clear();
+        self.filter_now();
     }
 
-    
+    /// Go back to the level below the open script menu level. Returns false
+    /// if no script menu is open.
+    pub fn pop_script_level(&mut self) -> bool {
+        let Some(level) = self.script_levels.pop() else {
+            return false;
+        };
+        self.entries = level.below;
+        self.query = level.below_query;
+        self.marked.clear();
+        self.filter_now();
+        true
+    }
 
+    /// Re-filter for the current query. Non-empty queries are matched on the
+    /// worker thread and applied once the result comes back.
     pub fn update_filter(&mut self) {
-
-        let group_config = self.config.groups.get(&self.active_group);
-
-        
+        self.filter_generation += 1;
 
         if self.query.is_empty() {
-
-            // Sort original entries by history for the "empty query" state
-
-            // We still need a list of all indices
-
-            let mut indices: Vec<usize> = (0..self.entries.len()).collect();
-
-            
-
-            indices.sort_by(|&a, &b| {
-
-                let a_entry = &self.entries[a];
-
-                let b_entry = &self.entries[b];
-
-                let a_count = self.history.usage_counts.get(&a_entry.id).unwrap_or(&0);
-
-                let b_count = self.history.usage_counts.get(&b_entry.id).unwrap_or(&0);
-
-                b_count.cmp(a_count).then_with(|| a_entry.name.cmp(&b_entry.name))
-
-            });
-
-            self.filtered_indices = indices;
-
+            self.sort_unfiltered();
         } else {
+            self.filter_pending = true;
+            self.match_worker.request(self.filter_generation, &self.query, &self.entries, self.config.general.match_mode);
+        }
+    }
 
-            // Update scores in place in the main entries list
-
-            self.matcher.match_entries(&self.query, &mut self.entries);
-
-            
-
-            // Apply history boost
-
-            for entry in self.entries.iter_mut() {
+    /// Re-filter synchronously, superseding any in-flight worker request.
+    pub fn filter_now(&mut self) {
+        self.filter_generation += 1;
 
-                if entry.score > 0 {
+        if self.query.is_empty() {
+            self.sort_unfiltered();
+        } else {
+            let matches = self.matcher.match_entries(&self.query, &self.entries, self.config.general.match_mode);
+            self.apply_matches(matches);
+        }
+    }
 
-                    let count = self.history.usage_counts.get(&entry.id).unwrap_or(&0);
+    /// Changes whenever the list is re-filtered.
+    pub fn generation(&self) -> u64 {
+        self.filter_generation
+    }
 
-                    entry.score += (*count as i64) * 100;
+    pub fn is_filter_pending(&self) -> bool {
+        self.filter_pending
+    }
 
-                }
+    /// Apply a result from the match worker. Returns false if it was stale.
+    pub fn handle_match_result(&mut self, result: MatchResult) -> bool {
+        if result.generation != self.filter_generation {
+            return false;
+        }
+        self.apply_matches(result.matches);
+        true
+    }
 
+    fn sort_unfiltered(&mut self) {
+        // Sort original entries by history for the "empty query" state
+        // We still need a list of all indices
+        let mut indices: Vec<usize> = (0..self.entries.len()).collect();
+        let now = history::now();
+
+        // Picked lines and script menus keep the order they were printed in
+        if !self.dmenu && self.script_levels.is_empty() {
+            let entries = &self.entries;
+            let usage = |entry: &Entry| self.history.usage(&self.active_group, &entry.id);
+            match self.config.general.default_sort {
+                DefaultSort::Frecency => indices.sort_by_cached_key(|&i| {
+                    let entry = &entries[i];
+                    (
+                        std::cmp::Reverse(self.history.frecency(&self.active_group, &entry.id, now)),
+                        entry.modified.map(std::cmp::Reverse),
+                        entry.name.clone(),
+                    )
+                }),
+                DefaultSort::Alpha => indices.sort_by_cached_key(|&i| {
+                    (entries[i].name.to_lowercase(), entries[i].name.clone())
+                }),
+                DefaultSort::Recent => indices.sort_by_cached_key(|&i| {
+                    let entry = &entries[i];
+                    (
+                        std::cmp::Reverse(usage(entry).and_then(|u| u.last_used())),
+                        entry.modified.map(std::cmp::Reverse),
+                        entry.name.clone(),
+                    )
+                }),
+                DefaultSort::Usage => indices.sort_by_cached_key(|&i| {
+                    let entry = &entries[i];
+                    (std::cmp::Reverse(usage(entry).map_or(0, |u| u.count)), entry.name.clone())
+                }),
             }
+        }
+        self.filtered_indices = indices;
+        self.apply_group_filters();
+    }
 
+    fn apply_matches(&mut self, mut matches: Vec<(usize, i64)>) {
+        // Apply history boost
+        let now = history::now();
+        for (idx, score) in matches.iter_mut() {
+            *score += self.history.frecency(&self.active_group, &self.entries[*idx].id, now);
+        }
 
+        // Sort by boosted score
+        matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
 
-            // Collect indices of matching entries
+        self.filtered_indices = matches.into_iter().map(|(idx, _)| idx).collect();
+        self.apply_group_filters();
+    }
 
-            let mut indices: Vec<usize> = self.entries.iter().enumerate()
+    fn apply_group_filters(&mut self) {
+        // Apply Blacklist/Whitelist from Group
+        if !self.dmenu && self.script_levels.is_empty()
+            && let Some(gc) = self.config.groups.get(&self.active_group) {
+            // Patterns are compiled once per group, not on every keystroke
+            let filter = match self.group_filter.take() {
+                Some((group, filter)) if group == self.active_group => filter,
+                _ => GroupFilter::new(gc),
+            };
+            let entries = &self.entries;
+            self.filtered_indices.retain(|&idx| filter.allows(&entries[idx]));
+            self.group_filter = Some((self.active_group.clone(), filter));
+        }
 
-                .filter(|(_, e)| e.score > 0)
+        self.sections = if self.dmenu || !self.script_levels.is_empty() {
+            Vec::new()
+        } else {
+            self.split_sections()
+        };
 
-                .map(|(i, _)| i)
+        // Computed entries go on top, whatever the group's filters say
+        self.query_entries = if self.dmenu || !self.script_levels.is_empty() {
+            Vec::new()
+        } else {
+            convert::convert(&self.query, &self.config.convert).into_iter().collect()
+        };
+        self.filtered_indices.splice(0..0, (0..self.query_entries.len()).map(|i| QUERY_ENTRY_BASE + i));
+        for (start, _) in self.sections.iter_mut() {
+            *start += self.query_entries.len();
+        }
 
-                .collect();
+        log::info!("AppState: query='{}', filtered_count={}", self.query, self.filtered_indices.len());
+        let same_query = self.filtered_query.as_deref() == Some(self.query.as_str());
+        self.selected_index = match self.keep_selected.take() {
+            Some(id) if same_query => self.filtered_indices.iter()
+                .position(|&idx| self.entry(idx).id == id)
+                .unwrap_or(0),
+            _ => 0,
+        };
+        self.filtered_query = Some(self.query.clone());
+        self.hover_index = None;
+        self.filter_pending = false;
+    }
+    
+    /// Reorder the list into the `grouped` sections, and return where each
+    /// starts. Sections come in the order of their best entry, and keep the
+    /// order within.
+    fn split_sections(&mut self) -> Vec<(usize, String)> {
+        let grouping = self.config.theme.grouped;
+        if grouping == Grouping::None {
+            return Vec::new();
+        }
 
+        let entries = &self.entries;
+        let mut labels: Vec<&str> = Vec::new();
+        let mut keyed: Vec<(usize, usize)> = self.filtered_indices.iter()
+            .map(|&idx| {
+                let label = section_label(grouping, &entries[idx]);
+                let section = labels.iter().position(|&l| l == label).unwrap_or_else(|| {
+                    labels.push(label);
+                    labels.len() - 1
+                });
+                (section, idx)
+            })
+            .collect();
+        // Stable, so each section keeps the match or history order
+        keyed.sort_by_key(|&(section, _)| section);
+
+        let mut sections = Vec::new();
+        for (position, &(section, _)) in keyed.iter().enumerate() {
+            if position == 0 || keyed[position - 1].0 != section {
+                sections.push((position, labels[section].to_string()));
+            }
+        }
+        self.filtered_indices = keyed.into_iter().map(|(_, idx)| idx).collect();
+        sections
+    }
 
+    /// Header of section `section`.
+    pub fn section_label(&self, section: usize) -> &str {
+        &self.sections[section].1
+    }
 
-            // Sort indices by entry score
+    /// Rows in the list, headers included.
+    pub fn row_count(&self) -> usize {
+        self.filtered_indices.len() + self.sections.len()
+    }
 
-            indices.sort_by(|&a, &b| {
+    /// What list row `row` shows. Header `j` is right before the section's
+    /// first entry, so `j` rows further down than it.
+    pub fn row(&self, row: usize) -> Row {
+        let mut position = row;
+        for (j, (start, _)) in self.sections.iter().enumerate() {
+            match (start + j).cmp(&row) {
+                std::cmp::Ordering::Equal => return Row::Header(j),
+                std::cmp::Ordering::Less => position -= 1,
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Row::Entry(position)
+    }
 
-                self.entries[b].score.cmp(&self.entries[a].score)
+    /// The list row of the entry at `position` in `filtered_indices`.
+    pub fn row_of(&self, position: usize) -> usize {
+        position + self.sections.iter().filter(|(start, _)| *start <= position).count()
+    }
 
-            });
+    /// Position in `filtered_indices` of the first entry on screen, which
+    /// quick-select digits count from.
+    pub fn first_visible_entry(&self, height: f32) -> usize {
+        let (scroll_offset, visible_items) = self.viewport(height);
+        (scroll_offset..scroll_offset + visible_items)
+            .find_map(|row| match self.row(row) {
+                Row::Entry(position) => Some(position),
+                Row::Header(_) => None,
+            })
+            .unwrap_or(0)
+    }
 
-            
+    /// The quick-select slot (0 for the 1 key, 9 for the 0 key) the entry at
+    /// `position` is numbered with, if it is on screen and has one.
+    pub fn quick_select_slot_of(&self, position: usize, height: f32) -> Option<usize> {
+        let (scroll_offset, visible_items) = self.viewport(height);
+        let row = self.row_of(position);
+        if row < scroll_offset || row >= scroll_offset + visible_items {
+            return None;
+        }
+        let slot = position.checked_sub(self.first_visible_entry(height))?;
+        (slot < self.config.general.quick_select_slots()).then_some(slot)
+    }
 
-            self.filtered_indices = indices;
+    /// Position of the entry quick-select `slot` picks, if it is on screen.
+    pub fn quick_select_target(&self, slot: usize, height: f32) -> Option<usize> {
+        if slot >= self.config.general.quick_select_slots() {
+            return None;
+        }
+        let (scroll_offset, visible_items) = self.viewport(height);
+        // Only rows on screen carry a number; headers don't count
+        let position = self.first_visible_entry(height) + slot;
+        (position < self.filtered_indices.len() && self.row_of(position) < scroll_offset + visible_items)
+            .then_some(position)
+    }
 
+    /// Replace the query with the selected entry's name, or with the longest
+    /// prefix all matches share when that is longer than what was typed.
+    /// Returns false if there was nothing to complete to.
+    pub fn complete(&mut self, mode: TabCompletion) -> bool {
+        let Some(&selected) = self.filtered_indices.get(self.selected_index) else {
+            return false;
         };
+        let mut completion = self.entry(selected).name.clone();
+
+        if mode == TabCompletion::Prefix {
+            let prefix_len = self.filtered_indices.iter()
+                .map(|&i| common_prefix_len(&completion, &self.entry(i).name))
+                .min()
+                .unwrap_or(0);
+            if prefix_len > self.query.len() {
+                completion.truncate(prefix_len);
+            }
+        }
 
+        if completion == self.query {
+            return false;
+        }
+        self.query = completion;
+        self.query_selected = false;
+        self.filter_now();
+        true
+    }
 
+    /// Mark the selected entry, or unmark it if it was marked. Computed
+    /// entries and script menu entries can't be marked. Returns false if
+    /// nothing changed.
+    pub fn toggle_mark(&mut self) -> bool {
+        let Some(&selected) = self.filtered_indices.get(self.selected_index) else {
+            return false;
+        };
+        if selected >= QUERY_ENTRY_BASE || self.config.script_source(&self.entries[selected].source).is_some() {
+            return false;
+        }
+        if !self.marked.remove(&selected) {
+            self.marked.insert(selected);
+        }
+        true
+    }
 
-        // Apply Blacklist/Whitelist from Group
-
-        if let Some(gc) = group_config {
-
-            let mut to_remove = Vec::new();
-
-            
-
-            // Prepare regexes once
-
-            let regexes: Vec<Regex> = gc.blacklist.as_ref()
-
-                .map(|bl| bl.iter().filter_map(|s| Regex::new(s).ok()).collect())
-
-                .unwrap_or_default();
-
+    /// What Enter acts on, as `filtered_indices` values: the marked entries
+    /// in list order, or just the selected one if none are marked. Marks
+    /// stay when the query changes, so entries marked for an earlier query
+    /// and filtered out since come last, in scan order.
+    pub fn picked(&self) -> Vec<usize> {
+        if self.marked.is_empty() {
+            return self.filtered_indices.get(self.selected_index).copied().into_iter().collect();
+        }
+        let mut picked: Vec<usize> = self.filtered_indices.iter()
+            .copied()
+            .filter(|idx| self.marked.contains(idx))
+            .collect();
+        let listed: HashSet<usize> = picked.iter().copied().collect();
+        let mut hidden: Vec<usize> = self.marked.iter().copied().filter(|idx| !listed.contains(idx)).collect();
+        hidden.sort_unstable();
+        picked.extend(hidden);
+        picked
+    }
 
+    pub fn move_selection(&mut self, delta: i32) {
+        if self.filtered_indices.is_empty() {
+            self.selected_index = 0;
+            return;
+        }
+        
+        let len = self.filtered_indices.len() as i32;
+        let new_index = (self.selected_index as i32 + delta).rem_euclid(len);
+        self.selected_index = new_index as usize;
+    }
+    
+    /// The rows a window `height` pixels tall shows: the first one (see
+    /// `row`) and how many fit. The selection is kept centred once the list
+    /// scrolls.
+    pub fn viewport(&self, height: f32) -> (usize, usize) {
+        let theme = &self.config.theme;
+        let visible_items = ((height - list_start_y(theme) - list_bottom(theme)) / item_height(theme)) as usize;
+
+        let total_items = self.row_count();
+        let selected_row = self.row_of(self.selected_index);
+        let scroll_offset = if total_items <= visible_items || selected_row < visible_items / 2 {
+            0
+        } else if selected_row >= total_items - visible_items / 2 {
+            total_items.saturating_sub(visible_items)
+        } else {
+            selected_row - visible_items / 2
+        };
+        (scroll_offset, visible_items)
+    }
 
-            for (i, &idx) in self.filtered_indices.iter().enumerate() {
+    /// Position in `filtered_indices` of the entry drawn at `y` in a window
+    /// `height` pixels tall, if there is one there.
+    pub fn entry_at(&self, y: f32, height: f32) -> Option<usize> {
+        let offset = y - list_start_y(&self.config.theme);
+        if offset < 0.0 {
+            return None;
+        }
+        let (scroll_offset, visible_items) = self.viewport(height);
+        let relative = (offset / item_height(&self.config.theme)) as usize;
+        if relative >= visible_items || scroll_offset + relative >= self.row_count() {
+            return None;
+        }
+        match self.row(scroll_offset + relative) {
+            Row::Entry(position) => Some(position),
+            Row::Header(_) => None,
+        }
+    }
 
-                let e = &self.entries[idx];
+    /// Placeholder shown while the query is empty, honoring group overrides.
+    pub fn placeholder(&self) -> &str {
+        self.config.groups.get(&self.active_group)
+            .and_then(|g| g.placeholder.as_deref())
+            .unwrap_or(&self.config.theme.placeholder)
+    }
 
-                
+    /// Prompt drawn in front of the typed query, honoring group overrides.
+    pub fn prompt(&self) -> &str {
+        self.config.groups.get(&self.active_group)
+            .and_then(|g| g.prompt.as_deref())
+            .unwrap_or(&self.config.theme.prompt)
+    }
+}
 
-                // Whitelist check
+/// A group's whitelist, blacklist and type filters, ready to apply.
+struct GroupFilter {
+    whitelist: Option<Vec<Regex>>,
+    blacklist: Vec<Regex>,
+    match_on: MatchOn,
+    include_types: Option<Vec<EntryType>>,
+    exclude_types: Vec<EntryType>,
+    categories: Option<Vec<String>>,
+}
 
-                if let Some(whitelist) = &gc.whitelist {
+#[cfg(test)]
+thread_local! {
+    /// How many `GroupFilter`s this thread has compiled
+    static FILTERS_COMPILED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
 
-                    if !whitelist.iter().any(|w| e.name.contains(w) || e.id.contains(w)) {
+impl GroupFilter {
+    fn new(group: &LaunchGroup) -> Self {
+        #[cfg(test)]
+        FILTERS_COMPILED.with(|n| n.set(n.get() + 1));
+        Self {
+            whitelist: group.whitelist.as_deref().map(compile_patterns),
+            blacklist: group.blacklist.as_deref().map(compile_patterns).unwrap_or_default(),
+            match_on: group.match_on,
+            include_types: group.include_types.as_ref()
+                .map(|types| types.iter().filter_map(|t| EntryType::from_name(t)).collect()),
+            exclude_types: group.exclude_types.iter().filter_map(|t| EntryType::from_name(t)).collect(),
+            categories: group.categories.clone(),
+        }
+    }
 
-                        to_remove.push(i);
+    /// Whether `e` passes the type and category filters, the whitelist and
+    /// the blacklist.
+    fn allows(&self, e: &Entry) -> bool {
+        if self.include_types.as_ref().is_some_and(|types| !types.contains(&e.entry_type))
+            || self.exclude_types.contains(&e.entry_type) {
+            return false;
+        }
 
-                        continue;
+        if let Some(categories) = &self.categories
+            && !e.categories.iter().any(|c| categories.iter().any(|wanted| wanted.eq_ignore_ascii_case(c))) {
+            return false;
+        }
 
-                    }
+        let matches = |re: &Regex| match self.match_on {
+            MatchOn::Name => re.is_match(&e.name),
+            MatchOn::Id => re.is_match(&e.id),
+            MatchOn::Both => re.is_match(&e.name) || re.is_match(&e.id),
+        };
+        if let Some(whitelist) = &self.whitelist
+            && !whitelist.iter().any(matches) {
+            return false;
+        }
+        !self.blacklist.iter().any(matches)
+    }
+}
 
-                }
+/// Header of the section `entry` goes in.
+fn section_label(grouping: Grouping, entry: &Entry) -> &str {
+    match grouping {
+        Grouping::Type => match entry.entry_type {
+            EntryType::Desktop => "Applications",
+            EntryType::Binary => "Commands",
+            EntryType::Custom => "Scripts and Actions",
+            EntryType::File => "Files",
+            EntryType::Link => "Links",
+            EntryType::History => "History",
+            EntryType::Line => "Lines",
+        },
+        Grouping::Source => match entry.source.as_str() {
+            "desktop" => "Applications",
+            "bin" => "Commands",
+            "scripts" => "Scripts",
+            "history" => "History",
+            "recent" => "Recent Files",
+            "power" => "Power",
+            // A group's static items
+            "" => "Other",
+            custom => custom,
+        },
+        Grouping::None => "",
+    }
+}
 
+/// Compile whitelist/blacklist patterns, logging and skipping invalid ones.
+fn compile_patterns(patterns: &[String]) -> Vec<Regex> {
+    patterns.iter()
+        .filter_map(|p| Regex::new(p).map_err(|e| log::warn!("Ignoring invalid pattern {:?}: {}", p, e)).ok())
+        .collect()
+}
 
+/// Byte length of the case-insensitive common prefix of `a` and `b`, on a
+/// char boundary of `a`.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(ca, cb)| ca.to_lowercase().eq(cb.to_lowercase()))
+        .map(|(ca, _)| ca.len_utf8())
+        .sum()
+}
 
-                // Blacklist check
+/// Drop bare binaries that a desktop entry already launches, keeping the
+/// desktop entry with its proper name and icon.
+fn dedupe_bin_desktop(entries: &mut Vec<Entry>) {
+    let desktop_execs: HashSet<String> = entries.iter()
+        .filter(|e| e.entry_type == EntryType::Desktop)
+        .filter_map(|e| e.executable_name())
+        .collect();
 
-                if !regexes.is_empty() && regexes.iter().any(|re| re.is_match(&e.name) || re.is_match(&e.id)) {
+    if desktop_execs.is_empty() {
+        return;
+    }
 
-                    to_remove.push(i);
+    entries.retain(|e| e.entry_type != EntryType::Binary || !desktop_execs.contains(&e.name));
+}
 
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            }
+    fn state_with(names: &[&str]) -> AppState {
+        let (tx, _) = calloop::channel::channel();
+        let mut state = AppState::new(Config::default(), MatchWorker::new(tx));
+        state.history = History::default();
+        state.add_entries(binaries(names));
+        state
+    }
 
-            
+    fn set_query(state: &mut AppState, query: &str) {
+        state.query = query.to_string();
+        state.filter_now();
+    }
 
-            // Remove in reverse to maintain index validity
+    fn position_of(state: &AppState, name: &str) -> usize {
+        state.filtered_indices.iter().position(|&idx| state.entry(idx).name == name).unwrap()
+    }
 
-            for i in to_remove.into_iter().rev() {
+    fn names(state: &AppState, picked: &[usize]) -> Vec<String> {
+        picked.iter().map(|&idx| state.entry(idx).name.clone()).collect()
+    }
 
-                self.filtered_indices.remove(i);
+    #[test]
+    fn picked_is_the_selection_without_marks() {
+        let mut state = state_with(&["alpha", "beta", "gamma"]);
+        state.selected_index = position_of(&state, "beta");
+        assert_eq!(names(&state, &state.picked()), ["beta"]);
+    }
 
-            }
+    #[test]
+    fn picked_follows_list_order_not_mark_order() {
+        let mut state = state_with(&["alpha", "beta", "gamma"]);
+        for name in ["gamma", "alpha"] {
+            state.selected_index = position_of(&state, name);
+            assert!(state.toggle_mark());
+        }
+        let expected: Vec<String> = state.filtered_indices.iter()
+            .map(|&idx| state.entry(idx).name.clone())
+            .filter(|name| name != "beta")
+            .collect();
+        assert_eq!(names(&state, &state.picked()), expected);
+    }
 
+    #[test]
+    fn picked_keeps_marks_hidden_by_the_query() {
+        let mut state = state_with(&["alpha", "beta", "gamma"]);
+        for name in ["alpha", "gamma"] {
+            state.selected_index = position_of(&state, name);
+            state.toggle_mark();
         }
+        set_query(&mut state, "gam");
+        assert_eq!(names(&state, &state.picked()), ["gamma", "alpha"]);
 
+        set_query(&mut state, "zzz");
+        assert!(state.filtered_indices.is_empty());
+        assert_eq!(names(&state, &state.picked()), ["alpha", "gamma"]);
+    }
 
+    #[test]
+    fn script_levels_stack_and_unwind() {
+        let mut state = state_with(&["menu"]);
+        set_query(&mut state, "men");
+        let level = |names: &[&str]| names.iter()
+            .map(|name| Entry::new(name.to_string(), name.to_string(), String::new(), EntryType::Custom, false))
+            .collect();
+
+        state.push_script_level(vec!["menu".to_string()], level(&["a", "b"]));
+        assert_eq!(state.script_selections(), ["menu"]);
+        assert!(state.query.is_empty());
+        assert_eq!(state.filtered_indices.len(), 2);
+
+        state.push_script_level(vec!["menu".to_string(), "a".to_string()], level(&["a1"]));
+        assert_eq!(state.script_selections(), ["menu", "a"]);
+        assert_eq!(names(&state, &state.filtered_indices), ["a1"]);
+
+        assert!(state.pop_script_level());
+        assert_eq!(state.script_selections(), ["menu"]);
+        assert!(state.pop_script_level());
+        assert!(state.script_selections().is_empty());
+        assert_eq!(state.query, "men");
+        assert_eq!(names(&state, &state.filtered_indices), ["menu"]);
+        assert!(!state.pop_script_level());
+    }
 
-        log::info!("AppState: query='{}', filtered_count={}", self.query, self.filtered_indices.len());
+    /// A window height that fits exactly `rows` result rows
+    fn height_for(state: &AppState, rows: usize) -> f32 {
+        let theme = &state.config.theme;
+        list_start_y(theme) + rows as f32 * item_height(theme) + list_bottom(theme)
+    }
 
-        self.selected_index = 0;
+    #[test]
+    fn viewport_keeps_the_selection_centred() {
+        let names: Vec<String> = (0..20).map(|i| format!("entry{:02}", i)).collect();
+        let mut state = state_with(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        let height = height_for(&state, 5);
 
+        for (selected, offset) in [(0, 0), (2, 0), (3, 1), (10, 8), (17, 15), (19, 15)] {
+            state.selected_index = selected;
+            assert_eq!(state.viewport(height), (offset, 5), "selected {}", selected);
+        }
     }
 
-    
-
-    pub fn move_selection(&mut self, delta: i32) {
+    #[test]
+    fn viewport_does_not_scroll_a_short_list() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.selected_index = 2;
+        assert_eq!(state.viewport(height_for(&state, 5)), (0, 5));
+    }
 
-        if self.filtered_indices.is_empty() {
+    #[test]
+    fn quick_select_keys_pick_the_rows_numbered_on_screen() {
+        let mut state = state_with(&[]);
+        let apps = (0..10).map(|i| {
+            let name = format!("app{:02}", i);
+            Entry::new(name.clone(), name, String::new(), EntryType::Desktop, false)
+        });
+        let commands: Vec<String> = (0..20).map(|i| format!("cmd{:02}", i)).collect();
+        let commands = binaries(&commands.iter().map(String::as_str).collect::<Vec<_>>());
+        state.add_entries(apps.chain(commands).collect());
+        let mut config = state.config.clone();
+        config.theme.grouped = Grouping::Type;
+        state.set_config(config);
+        let height = height_for(&state, 6);
+
+        // Rows 7..13: app06-app09, the "Commands" header, cmd00
+        state.selected_index = position_of(&state, "app09");
+        assert_eq!(state.viewport(height).0, 7);
+        assert_eq!(state.quick_select_target(0, height), Some(position_of(&state, "app06")));
+        assert_eq!(state.quick_select_target(4, height), Some(position_of(&state, "cmd00")));
+        assert_eq!(state.quick_select_target(5, height), None);
+
+        // Scrolled so the header is the top row, which gets no number
+        state.selected_index = position_of(&state, "cmd02");
+        assert!(matches!(state.row(state.viewport(height).0), Row::Header(_)));
+        assert_eq!(state.quick_select_target(0, height), Some(position_of(&state, "cmd00")));
+
+        for selected in 0..state.filtered_indices.len() {
+            state.selected_index = selected;
+            let (scroll_offset, visible_items) = state.viewport(height);
+            // The numbers `draw_frame` puts on its rows
+            let numbered: Vec<(usize, usize)> = (scroll_offset..state.row_count())
+                .take(visible_items)
+                .filter_map(|row| match state.row(row) {
+                    Row::Entry(position) => state.quick_select_slot_of(position, height).map(|slot| (slot, position)),
+                    Row::Header(_) => None,
+                })
+                .collect();
+            let picked: Vec<(usize, usize)> = (0..10)
+                .filter_map(|slot| state.quick_select_target(slot, height).map(|position| (slot, position)))
+                .collect();
+            assert_eq!(numbered, picked, "selected {}", selected);
+            assert_eq!(numbered.iter().map(|&(slot, _)| slot).collect::<Vec<_>>(), (0..numbered.len()).collect::<Vec<_>>());
+        }
+    }
 
-            self.selected_index = 0;
+    fn listed(state: &AppState) -> Vec<String> {
+        let mut names: Vec<String> = state.filtered_indices.iter().map(|&idx| state.entry(idx).name.clone()).collect();
+        names.sort();
+        names
+    }
 
-            return;
+    fn with_default_group(state: &AppState, edit: impl FnOnce(&mut LaunchGroup)) -> Config {
+        let mut config = state.config.clone();
+        edit(config.groups.get_mut("default").unwrap());
+        config
+    }
 
+    #[test]
+    fn group_patterns_follow_config_reloads() {
+        let mut state = state_with(&["alpha", "beta", "gamma"]);
+        let compiled = || FILTERS_COMPILED.with(|n| n.get());
+        let config = with_default_group(&state, |g| g.blacklist = Some(vec!["^b".to_string(), "(".to_string()]));
+        let before = compiled();
+        state.set_config(config);
+        assert_eq!(listed(&state), ["alpha", "gamma"]);
+        assert_eq!(compiled(), before + 1);
+
+        // Typing reuses the compiled patterns
+        for query in ["a", "al", ""] {
+            set_query(&mut state, query);
         }
+        assert_eq!(listed(&state), ["alpha", "gamma"]);
+        assert_eq!(compiled(), before + 1);
+
+        let config = with_default_group(&state, |g| {
+            g.blacklist = None;
+            g.whitelist = Some(vec!["^(alpha|beta)$".to_string()]);
+        });
+        set_query(&mut state, "a");
+        state.set_config(config);
+        // With a query the reload filters on the worker; do it here instead
+        state.filter_now();
+        assert_eq!(listed(&state), ["alpha", "beta"]);
+        assert_eq!(compiled(), before + 2);
+    }
 
-        
+    #[test]
+    fn group_categories_filter_by_any_listed_category() {
+        let mut state = state_with(&[]);
+        let entries = [("IDE", vec!["Development", "Utility"]), ("Game", vec!["Game"]), ("Bare", vec![])]
+            .into_iter()
+            .map(|(name, categories)| {
+                let mut entry = Entry::new(name.to_string(), name.to_string(), String::new(), EntryType::Desktop, false);
+                entry.categories = categories.into_iter().map(str::to_string).collect();
+                entry
+            })
+            .collect();
+        state.add_entries(entries);
+
+        let config = with_default_group(&state, |g| g.categories = Some(vec!["development".to_string(), "Office".to_string()]));
+        state.set_config(config);
+        assert_eq!(listed(&state), ["IDE"]);
+    }
 
-        let len = self.filtered_indices.len() as i32;
+    #[test]
+    fn toggle_mark_unmarks() {
+        let mut state = state_with(&["alpha"]);
+        assert!(state.toggle_mark());
+        assert!(state.toggle_mark());
+        assert!(state.marked.is_empty());
+    }
 
-        let new_index = (self.selected_index as i32 + delta).rem_euclid(len);
+    fn sorted_by(state: &mut AppState, sort: DefaultSort) -> Vec<String> {
+        let mut config = state.config.clone();
+        config.general.default_sort = sort;
+        state.set_config(config);
+        names(state, &state.filtered_indices)
+    }
 
-        self.selected_index = new_index as usize;
+    #[test]
+    fn empty_query_sorts_by_the_configured_order() {
+        const DAY: u64 = 24 * 60 * 60;
+        let mut state = state_with(&["Beta", "alpha", "carol", "dave"]);
+        let now = history::now();
+        state.history.record("default", "Beta", now);
+        for _ in 0..2 {
+            state.history.record("default", "dave", now - 10 * DAY);
+        }
+        for _ in 0..5 {
+            state.history.record("default", "carol", now - 100 * DAY);
+        }
 
+        // 1 launch today beats 5 a quarter ago, but not 2 last week
+        assert_eq!(sorted_by(&mut state, DefaultSort::Frecency), ["dave", "Beta", "carol", "alpha"]);
+        assert_eq!(sorted_by(&mut state, DefaultSort::Alpha), ["alpha", "Beta", "carol", "dave"]);
+        assert_eq!(sorted_by(&mut state, DefaultSort::Recent), ["Beta", "dave", "carol", "alpha"]);
+        assert_eq!(sorted_by(&mut state, DefaultSort::Usage), ["carol", "dave", "Beta", "alpha"]);
     }
 
-    
+    #[test]
+    fn empty_query_ties_fall_back_to_the_name() {
+        let mut state = state_with(&["delta", "bravo", "charlie"]);
+        for sort in [DefaultSort::Frecency, DefaultSort::Recent, DefaultSort::Usage] {
+            assert_eq!(sorted_by(&mut state, sort), ["bravo", "charlie", "delta"]);
+        }
+    }
 
-    pub fn get_selected(&self) -> Option<&Entry> {
+    fn selected_name(state: &AppState) -> String {
+        state.entry(state.filtered_indices[state.selected_index]).name.clone()
+    }
 
-        self.filtered_indices.get(self.selected_index)
+    fn binaries(names: &[&str]) -> Vec<Entry> {
+        names.iter()
+            .map(|name| Entry::new(name.to_string(), name.to_string(), name.to_string(), EntryType::Binary, false))
+            .collect()
+    }
 
-            .map(|&idx| &self.entries[idx])
+    #[test]
+    fn appending_entries_keeps_the_selection() {
+        let mut state = state_with(&["bravo", "delta"]);
+        state.selected_index = position_of(&state, "delta");
+        state.add_entries(binaries(&["alpha", "charlie"]));
+        assert_eq!(selected_name(&state), "delta");
+
+        // With a query the worker filters; the selection survives its result too
+        set_query(&mut state, "a");
+        state.selected_index = state.filtered_indices.len() - 1;
+        let selected = selected_name(&state);
+        state.add_entries(binaries(&["echo"]));
+        state.filter_now();
+        assert_ne!(state.selected_index, 0);
+        assert_eq!(selected_name(&state), selected);
+    }
 
+    #[test]
+    fn a_new_query_resets_the_selection() {
+        let mut state = state_with(&["bravo", "delta"]);
+        state.selected_index = position_of(&state, "delta");
+        state.add_entries(binaries(&["alpha"]));
+        set_query(&mut state, "a");
+        assert_eq!(state.selected_index, 0);
     }
 
+    #[test]
+    fn a_vanished_selection_falls_back_to_the_top() {
+        let from_test = |names: &[&str]| binaries(names).into_iter()
+            .map(|mut entry| {
+                entry.source = "test".to_string();
+                entry
+            })
+            .collect();
+        let mut state = state_with(&[]);
+        state.add_entries(from_test(&["bravo", "delta"]));
+        state.selected_index = position_of(&state, "delta");
+        state.replace_source("test", from_test(&["bravo", "charlie"]));
+        assert_eq!(state.selected_index, 0);
+    }
 }
@@ -0,0 +1,37 @@
+use std::time::{Duration, Instant};
+
+/// Startup step durations, printed to stderr with `--timings` so a slow
+/// source or a slow first frame can be spotted without a profiler.
+#[derive(Clone, Copy)]
+pub struct Timings {
+    start: Instant,
+    enabled: bool,
+}
+
+impl Timings {
+    /// Start counting from now, which should be as early in `main` as possible.
+    pub fn new(enabled: bool) -> Self {
+        Self { start: Instant::now(), enabled }
+    }
+
+    /// Report that `step` just finished after taking `took`. Safe to call
+    /// from any thread; eprintln holds the stderr lock for the whole line.
+    pub fn report(&self, step: &str, took: Duration) {
+        if self.enabled {
+            eprintln!(
+                "timings: {:<24} {:>9.2}ms  (at {:.2}ms)",
+                step,
+                took.as_secs_f64() * 1000.0,
+                self.start.elapsed().as_secs_f64() * 1000.0,
+            );
+        }
+    }
+
+    /// Run `f` and report how long it took as `step`.
+    pub fn time<T>(&self, step: &str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.report(step, started.elapsed());
+        result
+    }
+}
@@ -1,43 +1,91 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::panic;
 use std::path::{Path, PathBuf};
 use tiny_skia::{Pixmap, Transform};
 use image::ImageReader;
 use std::fs;
-use std::sync::mpsc::{Sender, channel};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
 use std::thread;
+use std::time::UNIX_EPOCH;
+use std::num::NonZeroUsize;
+use lru::LruCache;
+use crate::config::GeneralConfig;
+
+/// Enough for every row of a full-screen list, so icons on screen never
+/// evict each other and reload in a loop
+const MIN_CACHE_SIZE: usize = 128;
+
+/// Icons loaded in parallel at most. Lookups mostly wait on the disk, so
+/// more threads than this rarely help.
+const MAX_WORKERS: usize = 4;
+
+/// Larger icon files are skipped rather than decoded; themes ship icons far
+/// below these, so anything bigger is broken or not really an icon
+const MAX_ICON_FILE_BYTES: u64 = 16 * 1024 * 1024;
+const MAX_ICON_DIMENSION: u32 = 4096;
+/// What a decoder may allocate for one icon
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
 
 pub struct IconCache {
-    pub cache: HashMap<String, Option<Pixmap>>,
+    /// Loaded icons, `None` for ones that couldn't be found. Bounded so a
+    /// long-running instance doesn't keep every icon it ever drew.
+    pub cache: LruCache<String, Option<Pixmap>>,
     pending: HashSet<String>,
-    icon_theme_paths: Vec<PathBuf>,
-    request_tx: Sender<(String, u32)>,
+    /// The pending icons that were only prefetched, which the loader gets
+    /// to after everything on screen
+    prefetching: HashSet<String>,
+    /// Icons asked for since the last `cancel_stale`
+    wanted: HashSet<String>,
+    queue: Arc<RequestQueue>,
+}
+
+struct IconRequest {
+    name: String,
+    size: u32,
+    prefetch: bool,
 }
 
 impl IconCache {
-    pub fn new(response_tx: calloop::channel::Sender<(String, Option<Pixmap>)>) -> Self {
-        let mut paths = Vec::new();
-        if let Some(home) = directories::BaseDirs::new() {
-            paths.push(home.data_dir().join("icons"));
+    pub fn new(
+        response_tx: calloop::channel::Sender<(String, Option<Pixmap>)>,
+        capacity: usize,
+        disk_cache: Option<DiskCache>,
+        icon_theme: Option<String>,
+    ) -> Self {
+        let queue = Arc::new(RequestQueue::default());
+        // Built by whichever worker gets the first request, off the main thread
+        let loader = Arc::new(OnceLock::new());
+        let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_WORKERS);
+
+        for _ in 0..workers {
+            let (queue, loader, response_tx) = (Arc::clone(&queue), Arc::clone(&loader), response_tx.clone());
+            let (disk_cache, icon_theme) = (disk_cache.clone(), icon_theme.clone());
+            thread::spawn(move || {
+                while let Some(request) = queue.next() {
+                    let loader = loader.get_or_init(|| IconLoader::new(disk_cache.clone(), icon_theme.as_deref()));
+                    // A decoder choking on a broken file costs that icon, not the
+                    // worker. This relies on panics unwinding, which a build with
+                    // `panic = "abort"` would turn off.
+                    let load = panic::AssertUnwindSafe(|| loader.find_and_load(&request.name, request.size));
+                    let pixmap = panic::catch_unwind(load).unwrap_or_else(|_| {
+                        log::warn!("Loading icon {} panicked", request.name);
+                        None
+                    });
+                    if response_tx.send((request.name, pixmap)).is_err() {
+                        break;
+                    }
+                }
+            });
         }
-        paths.push(PathBuf::from("/usr/share/icons"));
-        paths.push(PathBuf::from("/usr/share/pixmaps"));
-        
-        let (request_tx, request_rx) = channel::<(String, u32)>();
-
-        let paths_clone = paths.clone();
-        thread::spawn(move || {
-            let loader = IconLoader { icon_theme_paths: paths_clone };
-            while let Ok((icon_name, size)) = request_rx.recv() {
-                let pixmap = loader.find_and_load(&icon_name, size);
-                let _ = response_tx.send((icon_name, pixmap));
-            }
-        });
 
         Self {
-            cache: HashMap::new(),
+            cache: LruCache::new(NonZeroUsize::new(capacity.max(MIN_CACHE_SIZE)).unwrap()),
             pending: HashSet::new(),
-            icon_theme_paths: paths,
-            request_tx,
+            prefetching: HashSet::new(),
+            wanted: HashSet::new(),
+            queue,
         }
     }
 
@@ -46,84 +94,395 @@ impl IconCache {
             return cached.clone();
         }
 
+        self.wanted.insert(icon_name.to_string());
         if !self.pending.contains(icon_name) {
             self.pending.insert(icon_name.to_string());
-            let _ = self.request_tx.send((icon_name.to_string(), size));
+            self.request(icon_name, size, false);
+        } else if self.prefetching.remove(icon_name) {
+            // Scrolled into view while waiting behind other prefetches
+            self.request(icon_name, size, false);
         }
 
         None
     }
 
+    /// Start loading `icon_name` in the background, after any icon asked for
+    /// by `get`, unless it's loaded or loading already. Returns false if it
+    /// is known not to exist, so the caller can try the next candidate.
+    pub fn prefetch(&mut self, icon_name: &str, size: u32) -> bool {
+        match self.cache.peek(icon_name) {
+            Some(cached) => cached.is_some(),
+            None => {
+                self.wanted.insert(icon_name.to_string());
+                if self.pending.insert(icon_name.to_string()) {
+                    self.prefetching.insert(icon_name.to_string());
+                    self.request(icon_name, size, true);
+                }
+                true
+            }
+        }
+    }
+
+    /// Drop queued requests for icons that weren't asked for (by `get` or
+    /// `prefetch`) since the last call, e.g. for rows scrolled past. Call
+    /// once per frame, after asking for everything it needs.
+    pub fn cancel_stale(&mut self) {
+        let wanted = std::mem::take(&mut self.wanted);
+        for name in self.queue.cancel(|name| wanted.contains(name)) {
+            self.pending.remove(&name);
+            self.prefetching.remove(&name);
+        }
+    }
+
+    fn request(&self, icon_name: &str, size: u32, prefetch: bool) {
+        self.queue.push(IconRequest { name: icon_name.to_string(), size, prefetch });
+    }
+
+    /// Whether `icon_name` is still being loaded in the background.
+    pub fn is_pending(&self, icon_name: &str) -> bool {
+        self.pending.contains(icon_name)
+    }
+
     pub fn insert(&mut self, name: String, pixmap: Option<Pixmap>) {
-        self.cache.insert(name.clone(), pixmap);
         self.pending.remove(&name);
+        self.prefetching.remove(&name);
+        self.cache.put(name, pixmap);
+    }
+}
+
+impl Drop for IconCache {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+/// Icons waiting for a worker, shared between the cache and its workers.
+#[derive(Default)]
+struct RequestQueue {
+    queued: Mutex<Queued>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Queued {
+    /// Icons on screen, oldest first. The newest go first, as the list may
+    /// have scrolled on since the older ones were asked for.
+    visible: Vec<IconRequest>,
+    /// Prefetches, loaded in order once nothing on screen is waiting
+    prefetch: VecDeque<IconRequest>,
+    closed: bool,
+}
+
+impl RequestQueue {
+    fn push(&self, request: IconRequest) {
+        let mut queued = self.queued.lock().unwrap();
+        if request.prefetch {
+            queued.prefetch.push_back(request);
+        } else {
+            // Promoted from a prefetch; don't load it twice
+            queued.prefetch.retain(|waiting| waiting.name != request.name);
+            queued.visible.push(request);
+        }
+        self.ready.notify_one();
+    }
+
+    /// The next icon to load, waiting for one if there's none. None once
+    /// the cache is gone.
+    fn next(&self) -> Option<IconRequest> {
+        let mut queued = self.queued.lock().unwrap();
+        loop {
+            if queued.closed {
+                return None;
+            }
+            if let Some(request) = queued.visible.pop().or_else(|| queued.prefetch.pop_front()) {
+                return Some(request);
+            }
+            queued = self.ready.wait(queued).unwrap();
+        }
+    }
+
+    /// Drop the requests for which `keep` is false, returning their names.
+    fn cancel(&self, keep: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut queued = self.queued.lock().unwrap();
+        let mut cancelled = Vec::new();
+        let Queued { visible, prefetch, .. } = &mut *queued;
+        visible.retain(|request| keep(&request.name) || { cancelled.push(request.name.clone()); false });
+        prefetch.retain(|request| keep(&request.name) || { cancelled.push(request.name.clone()); false });
+        cancelled
+    }
+
+    fn close(&self) {
+        self.queued.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// Rendered icons saved as PNGs, so later runs skip decoding and scaling
+/// them. Files are named after the source file, its mtime and the size, so
+/// an icon that changes on disk is simply rendered again.
+#[derive(Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl DiskCache {
+    /// The cache `icon_disk_cache_dir` and `icon_disk_cache_mb` ask for,
+    /// None when it's turned off.
+    pub fn from_config(general: &GeneralConfig) -> Option<Self> {
+        if general.icon_disk_cache_mb == 0 {
+            return None;
+        }
+        let dir = match &general.icon_disk_cache_dir {
+            Some(dir) => PathBuf::from(crate::executor::expand_for_group(dir, None)),
+            None => directories::ProjectDirs::from("org", "runner", "runner")?.cache_dir().join("icons"),
+        };
+        Some(Self { dir, max_bytes: general.icon_disk_cache_mb * 1024 * 1024 })
+    }
+
+    fn path(&self, source: &Path, size: u32) -> Option<PathBuf> {
+        let modified = fs::metadata(source).ok()?.modified().ok()?;
+        let mut hasher = DefaultHasher::new();
+        (source, modified.duration_since(UNIX_EPOCH).ok()?.as_nanos(), size).hash(&mut hasher);
+        Some(self.dir.join(format!("{:016x}.png", hasher.finish())))
+    }
+
+    fn load(&self, source: &Path, size: u32) -> Option<Pixmap> {
+        Pixmap::load_png(self.path(source, size)?).ok()
+    }
+
+    fn store(&self, source: &Path, size: u32, pixmap: &Pixmap) {
+        let Some(path) = self.path(source, size) else { return };
+        let result = pixmap.encode_png()
+            .map_err(anyhow::Error::from)
+            .and_then(|png| crate::sources::history::write_atomic(&path, &png));
+        if let Err(e) = result {
+            log::debug!("Failed to cache {:?} as {:?}: {}", source, path, e);
+        }
+    }
+
+    /// Create the directory, and delete the oldest files until what's left
+    /// fits in `max_bytes`.
+    fn prune(&self) {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            log::warn!("Failed to create the icon cache {:?}: {}", self.dir, e);
+            return;
+        }
+        let Ok(read_dir) = fs::read_dir(&self.dir) else { return };
+        let mut files: Vec<_> = read_dir.flatten()
+            .filter_map(|file| {
+                let metadata = file.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), file.path()))
+            })
+            .collect();
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort();
+        for (_, len, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
     }
 }
 
+/// Icon lookup following the freedesktop icon theme spec: the user's theme,
+/// then the themes it inherits from, then hicolor, then unthemed pixmaps.
 struct IconLoader {
-    icon_theme_paths: Vec<PathBuf>,
+    /// Directories holding icon themes, in lookup order
+    base_dirs: Vec<PathBuf>,
+    /// The active theme followed by its Inherits chain, ending with hicolor
+    themes: Vec<IconTheme>,
+    disk_cache: Option<DiskCache>,
+}
+
+struct IconTheme {
+    name: String,
+    /// Subdirectories listed in the theme's `Directories=`, in order
+    directories: Vec<ThemeDir>,
+}
+
+/// One subdirectory of a theme, e.g. "48x48/apps", with the sizes its
+/// `index.theme` section says it holds
+struct ThemeDir {
+    path: String,
+    size: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    scale: u32,
+    kind: DirKind,
+}
+
+#[derive(PartialEq)]
+enum DirKind {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+impl ThemeDir {
+    fn from_section(path: &str, section: &HashMap<String, String>) -> Option<Self> {
+        let int = |key: &str| section.get(key).and_then(|v| v.parse::<u32>().ok());
+        let size = int("Size")?;
+        let kind = match section.get("Type").map(String::as_str) {
+            Some("Fixed") => DirKind::Fixed,
+            Some("Scalable") => DirKind::Scalable,
+            _ => DirKind::Threshold,
+        };
+        Some(Self {
+            path: path.to_string(),
+            size,
+            min_size: int("MinSize").unwrap_or(size),
+            max_size: int("MaxSize").unwrap_or(size),
+            threshold: int("Threshold").unwrap_or(2),
+            scale: int("Scale").unwrap_or(1),
+            kind,
+        })
+    }
+
+    /// How far this directory's icons are from `size` at scale 1, per the
+    /// icon theme spec's DirectorySizeDistance. Zero means a match.
+    fn size_distance(&self, size: u32) -> u32 {
+        let scaled = |s: u32| s * self.scale;
+        let (low, high) = match self.kind {
+            DirKind::Fixed => (self.size, self.size),
+            DirKind::Scalable => (self.min_size, self.max_size),
+            DirKind::Threshold => (self.size.saturating_sub(self.threshold), self.size + self.threshold),
+        };
+        if self.scale == 1 && (low..=high).contains(&size) {
+            0
+        } else if size < scaled(low) {
+            scaled(low) - size
+        } else {
+            size.saturating_sub(scaled(high)).max(1)
+        }
+    }
 }
 
 impl IconLoader {
+    /// Search `icon_theme` if given and installed, else GTK's theme.
+    fn new(disk_cache: Option<DiskCache>, icon_theme: Option<&str>) -> Self {
+        if let Some(disk_cache) = &disk_cache {
+            disk_cache.prune();
+        }
+        Self::with_theme_dirs(icon_base_dirs(), icon_theme, current_icon_theme, disk_cache)
+    }
+
+    /// `new` with the directories themes are installed in and the source of
+    /// GTK's theme name given, which is only asked when `icon_theme` isn't used.
+    fn with_theme_dirs(
+        base_dirs: Vec<PathBuf>,
+        icon_theme: Option<&str>,
+        gtk_theme: impl FnOnce() -> Option<String>,
+        disk_cache: Option<DiskCache>,
+    ) -> Self {
+        let mut themes = Vec::new();
+        let mut seen = HashSet::new();
+        let configured = icon_theme.filter(|name| {
+            let installed = base_dirs.iter().any(|base| base.join(name).join("index.theme").is_file());
+            if !installed {
+                log::warn!("Icon theme {:?} is not installed, using the GTK theme", name);
+            }
+            installed
+        });
+        // Adwaita is GTK's default when no theme is configured
+        let name = configured.map(str::to_string)
+            .or_else(gtk_theme)
+            .unwrap_or_else(|| "Adwaita".to_string());
+        push_theme_chain(&name, &base_dirs, &mut themes, &mut seen);
+        // hicolor is every theme's implicit last parent
+        seen.remove("hicolor");
+        push_theme_chain("hicolor", &base_dirs, &mut themes, &mut seen);
+
+        log::debug!("Icon themes: {:?}", themes.iter().map(|t| &t.name).collect::<Vec<_>>());
+        Self { base_dirs, themes, disk_cache }
+    }
+
     fn find_and_load(&self, icon_name: &str, size: u32) -> Option<Pixmap> {
         let path = Path::new(icon_name);
-        if path.is_absolute() && path.exists() {
-             return self.load_from_path(path, size);
-        }
-
-        for root in &self.icon_theme_paths {
-            if !root.exists() { continue; }
-            
-            let common_subdirs = [
-                "hicolor/48x48/apps",
-                "hicolor/scalable/apps",
-                "hicolor/32x32/apps",
-                "hicolor/64x64/apps",
-                "Adwaita/48x48/apps",
-                "Adwaita/scalable/apps",
-                "",
-            ];
-
-            for sub in common_subdirs {
-                let dir = root.join(sub);
-                if !dir.exists() { continue; }
-                
-                let extensions = ["png", "svg", "xpm"];
-                for ext in extensions {
-                    let file_path = dir.join(format!("{}.{}", icon_name, ext));
-                    if file_path.exists() {
-                        return self.load_from_path(&file_path, size);
+        let file_path = if path.is_absolute() && path.exists() {
+            path.to_path_buf()
+        } else {
+            self.find_themed(icon_name, size).or_else(|| self.find_unthemed(icon_name))?
+        };
+
+        if let Some(pixmap) = self.disk_cache.as_ref().and_then(|cache| cache.load(&file_path, size)) {
+            return Some(pixmap);
+        }
+        let pixmap = self.load_from_path(&file_path, size)?;
+        if let Some(cache) = &self.disk_cache {
+            cache.store(&file_path, size, &pixmap);
+        }
+        Some(pixmap)
+    }
+
+    /// The file in the first theme that has the icon at all, from the
+    /// directory whose size is closest to `size`. An exact size match wins
+    /// straight away; otherwise a scalable SVG usually does, which beats
+    /// upscaling a small raster.
+    fn find_themed(&self, icon_name: &str, size: u32) -> Option<PathBuf> {
+        for theme in &self.themes {
+            let mut closest: Option<(u32, PathBuf)> = None;
+            for dir in &theme.directories {
+                let distance = dir.size_distance(size);
+                if closest.as_ref().is_some_and(|(best, _)| *best <= distance) {
+                    continue;
+                }
+                let found = self.base_dirs.iter()
+                    .find_map(|base| find_in_dir(&base.join(&theme.name).join(&dir.path), icon_name));
+                if let Some(file) = found {
+                    if distance == 0 {
+                        return Some(file);
                     }
+                    closest = Some((distance, file));
                 }
             }
+            if let Some((_, file)) = closest {
+                return Some(file);
+            }
         }
         None
     }
 
+    /// Icons installed directly in a base directory, e.g. /usr/share/pixmaps
+    fn find_unthemed(&self, icon_name: &str) -> Option<PathBuf> {
+        self.base_dirs.iter()
+            .chain(std::iter::once(&PathBuf::from("/usr/share/pixmaps")))
+            .find_map(|dir| find_in_dir(dir, icon_name))
+    }
+
     fn load_from_path(&self, path: &Path, size: u32) -> Option<Pixmap> {
+        let len = fs::metadata(path).ok()?.len();
+        if len > MAX_ICON_FILE_BYTES {
+            log::warn!("Skipping icon {:?}: {} bytes is too big", path, len);
+            return None;
+        }
         let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
         match ext {
             "svg" => self.load_svg(path, size),
+            // The image crate can't read XPM
+            "xpm" => scale_raster(decode_xpm(&fs::read_to_string(path).ok()?)?, size),
             _ => self.load_raster(path, size),
         }
     }
 
     fn load_raster(&self, path: &Path, size: u32) -> Option<Pixmap> {
-        let img = ImageReader::open(path).ok()?.decode().ok()?;
-        let img = img.resize(size, size, image::imageops::FilterType::Lanczos3);
-        let mut rgba = img.into_rgba8();
-        
-        for pixel in rgba.chunks_exact_mut(4) {
-            let a = pixel[3] as f32 / 255.0;
-            pixel[0] = (pixel[0] as f32 * a) as u8;
-            pixel[1] = (pixel[1] as f32 * a) as u8;
-            pixel[2] = (pixel[2] as f32 * a) as u8;
+        let mut reader = ImageReader::open(path).ok()?;
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(MAX_ICON_DIMENSION);
+        limits.max_image_height = Some(MAX_ICON_DIMENSION);
+        limits.max_alloc = Some(MAX_DECODE_ALLOC);
+        reader.limits(limits);
+        match reader.decode() {
+            Ok(image) => scale_raster(image.into_rgba8(), size),
+            Err(e) => {
+                log::debug!("Failed to decode icon {:?}: {}", path, e);
+                None
+            }
         }
-
-        let width = rgba.width();
-        let height = rgba.height();
-        
-        Pixmap::from_vec(rgba.into_vec(), tiny_skia::IntSize::from_wh(width, height)?)
     }
 
     fn load_svg(&self, path: &Path, size: u32) -> Option<Pixmap> {
@@ -140,4 +499,322 @@ impl IconLoader {
         resvg::render(&tree, transform, &mut pixmap.as_mut());
         Some(pixmap)
     }
-}
\ No newline at end of file
+}
+
+/// Scale a decoded raster icon to fit in `size` x `size`, premultiplied as
+/// a pixmap wants it.
+fn scale_raster(mut rgba: image::RgbaImage, size: u32) -> Option<Pixmap> {
+    // Premultiply before scaling: filtering straight alpha mixes in the
+    // (usually black) color of fully transparent pixels, which shows up
+    // as dark fringes around the icon
+    for pixel in rgba.chunks_exact_mut(4) {
+        let a = pixel[3] as f32 / 255.0;
+        for c in &mut pixel[..3] {
+            *c = (*c as f32 * a).round() as u8;
+        }
+    }
+    let mut rgba = image::DynamicImage::ImageRgba8(rgba)
+        .resize(size, size, image::imageops::FilterType::Lanczos3)
+        .into_rgba8();
+    // Lanczos overshoots at sharp edges; premultiplied color can't exceed alpha
+    for pixel in rgba.chunks_exact_mut(4) {
+        let a = pixel[3];
+        for c in &mut pixel[..3] {
+            *c = (*c).min(a);
+        }
+    }
+
+    let width = rgba.width();
+    let height = rgba.height();
+    
+    Pixmap::from_vec(rgba.into_vec(), tiny_skia::IntSize::from_wh(width, height)?)
+}
+
+/// Decode an XPM3 image: a C array of strings holding the size and
+/// palette, then one string per row of `chars_per_pixel`-wide pixel keys.
+/// Only each color's `c` (color visual) value is used.
+fn decode_xpm(content: &str) -> Option<image::RgbaImage> {
+    // The quoted strings, in order; comments and C syntax in between are skipped
+    let mut strings = content.split('"').skip(1).step_by(2);
+
+    let mut header = strings.next()?.split_whitespace().map(|n| n.parse::<u32>().ok());
+    let (width, height, colors, chars_per_pixel) = (header.next()??, header.next()??, header.next()??, header.next()?? as usize);
+    if chars_per_pixel == 0 || width > MAX_ICON_DIMENSION || height > MAX_ICON_DIMENSION {
+        return None;
+    }
+
+    let mut palette = HashMap::new();
+    for _ in 0..colors {
+        let line = strings.next()?;
+        let key = line.get(..chars_per_pixel)?;
+        palette.insert(key, xpm_color(line.get(chars_per_pixel..)?));
+    }
+
+    let mut image = image::RgbaImage::new(width, height);
+    for y in 0..height {
+        let row = strings.next()?;
+        for x in 0..width {
+            let start = x as usize * chars_per_pixel;
+            let key = row.get(start..start + chars_per_pixel)?;
+            image.put_pixel(x, y, image::Rgba(*palette.get(key)?));
+        }
+    }
+    Some(image)
+}
+
+/// The `c` color of an XPM palette entry like `" c #ff0000 m black"`:
+/// `None` for transparent, hex with 1 to 4 digits per channel, or a CSS
+/// color name. Anything else is drawn black.
+fn xpm_color(spec: &str) -> [u8; 4] {
+    let tokens: Vec<&str> = spec.split_whitespace().collect();
+    // A name may have spaces ("light gray") and runs to the next key
+    let Some(start) = tokens.iter().position(|&t| t == "c") else { return [0, 0, 0, 255] };
+    let end = tokens[start + 1..].iter()
+        .position(|t| ["m", "g", "g4", "s"].contains(t))
+        .map_or(tokens.len(), |i| start + 1 + i);
+    let value = tokens[start + 1..end].join(" ");
+
+    if value.eq_ignore_ascii_case("none") {
+        return [0, 0, 0, 0];
+    }
+    if let Some(hex) = value.strip_prefix('#')
+        && hex.len() % 3 == 0
+        && hex.len() > 6
+        && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        // 12 digits (#rrrrggggbbbb) and the like: keep the top byte of each
+        let width = hex.len() / 3;
+        let channel = |i: usize| u8::from_str_radix(&hex[i * width..i * width + 2], 16).unwrap_or(0);
+        return [channel(0), channel(1), channel(2), 255];
+    }
+    let color = crate::config::ThemeConfig::try_parse_color(&value.replace(' ', "")).unwrap_or(tiny_skia::Color::BLACK);
+    let color = color.to_color_u8();
+    [color.red(), color.green(), color.blue(), 255]
+}
+
+fn find_in_dir(dir: &Path, icon_name: &str) -> Option<PathBuf> {
+    ["png", "svg", "xpm"].iter()
+        .map(|ext| dir.join(format!("{}.{}", icon_name, ext)))
+        .find(|path| path.exists())
+}
+
+/// `~/.icons`, `$XDG_DATA_HOME/icons` and `icons` in each of `$XDG_DATA_DIRS`
+fn icon_base_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(base) = directories::BaseDirs::new() {
+        dirs.push(base.home_dir().join(".icons"));
+    }
+    dirs.extend(crate::sources::xdg_data_dirs().into_iter().map(|d| d.join("icons")));
+    dirs.retain(|d| d.is_dir());
+    dirs
+}
+
+/// The icon theme name GTK is configured with, if any.
+fn current_icon_theme() -> Option<String> {
+    let config_dir = directories::BaseDirs::new()?.config_dir().to_path_buf();
+    ["gtk-4.0", "gtk-3.0"].iter()
+        .filter_map(|gtk| fs::read_to_string(config_dir.join(gtk).join("settings.ini")).ok())
+        .find_map(|content| parse_ini(&content).get_mut("Settings")?.remove("gtk-icon-theme-name"))
+}
+
+/// Add `name` and, depth first, the themes it inherits from.
+fn push_theme_chain(name: &str, base_dirs: &[PathBuf], themes: &mut Vec<IconTheme>, seen: &mut HashSet<String>) {
+    if !seen.insert(name.to_string()) {
+        return;
+    }
+    let Some(index) = base_dirs.iter()
+        .find_map(|base| fs::read_to_string(base.join(name).join("index.theme")).ok()) else {
+        log::debug!("Icon theme {} not found", name);
+        return;
+    };
+    let sections = parse_ini(&index);
+
+    let list = |key| sections.get("Icon Theme")
+        .and_then(|s| s.get(key))
+        .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let directories = list("Directories").into_iter()
+        .chain(list("ScaledDirectories"))
+        .filter_map(|path| ThemeDir::from_section(&path, sections.get(&path)?))
+        .collect();
+    themes.push(IconTheme { name: name.to_string(), directories });
+
+    for parent in list("Inherits") {
+        if parent != "hicolor" {
+            push_theme_chain(&parent, base_dirs, themes, seen);
+        }
+    }
+}
+
+/// Keys of an ini-style file, by section name
+fn parse_ini(content: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(name.to_string());
+        } else if let Some(section) = &current
+            && let Some((key, value)) = line.split_once('=') {
+            sections.entry(section.clone()).or_default()
+                .entry(key.trim().to_string())
+                .or_insert_with(|| value.trim().to_string());
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory unique to this test run
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runner-icons-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn bare_loader() -> IconLoader {
+        IconLoader { base_dirs: Vec::new(), themes: Vec::new(), disk_cache: None }
+    }
+
+    const RED_DOT: &str = "/* XPM */\nstatic char *dot[] = {\n\"1 1 1 1\",\n\"r c #ff0000\",\n\"r\"\n};\n";
+
+    #[test]
+    fn oversized_icon_files_are_skipped() {
+        let dir = temp_dir("oversized");
+        let small = dir.join("small.xpm");
+        fs::write(&small, RED_DOT).unwrap();
+        assert!(bare_loader().load_from_path(&small, 16).is_some());
+
+        // Still a valid XPM, just padded past the limit with a comment
+        let padding = "x".repeat(MAX_ICON_FILE_BYTES as usize);
+        let big = dir.join("big.xpm");
+        fs::write(&big, format!("{}/* {} */", RED_DOT, padding)).unwrap();
+        assert!(bare_loader().load_from_path(&big, 16).is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn xpm_dimensions_over_the_limit_are_rejected() {
+        let too_wide = format!("\"{} 1 1 1\", \"a c #000000\", \"a\"", MAX_ICON_DIMENSION + 1);
+        assert!(decode_xpm(&too_wide).is_none());
+        let too_tall = format!("\"1 {} 1 1\", \"a c #000000\", \"a\"", MAX_ICON_DIMENSION + 1);
+        assert!(decode_xpm(&too_tall).is_none());
+    }
+
+    #[test]
+    fn xpm_none_is_transparent() {
+        let image = decode_xpm("\"2 1 2 1\", \". c None\", \"# c #00ff00\", \".#\"").unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn xpm_keys_can_span_several_characters() {
+        let xpm = "\"2 2 2 2\", \"a. c #ff0000\", \"b. c #0000ff\", \"a.b.\", \"b.a.\"";
+        let image = decode_xpm(xpm).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(1, 1).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn xpm_colors() {
+        assert_eq!(xpm_color(" c #ffff80800000"), [255, 128, 0, 255]);
+        assert_eq!(xpm_color(" c #123456"), [0x12, 0x34, 0x56, 255]);
+        assert_eq!(xpm_color(" c #f00"), [255, 0, 0, 255]);
+        assert_eq!(xpm_color(" c light gray"), [0xd3, 0xd3, 0xd3, 255]);
+        // The name ends where the next key starts
+        assert_eq!(xpm_color(" c navy m black"), [0, 0, 0x80, 255]);
+        assert_eq!(xpm_color(" m white c NONE"), [0, 0, 0, 0]);
+        assert_eq!(xpm_color(" m white"), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn truncated_xpm_is_none() {
+        // Missing the second row
+        assert!(decode_xpm("\"1 2 1 1\", \"a c #000000\", \"a\"").is_none());
+        // A row shorter than the width
+        assert!(decode_xpm("\"2 1 1 1\", \"a c #000000\", \"a\"").is_none());
+        // Missing palette entries
+        assert!(decode_xpm("\"1 1 2 1\", \"a c #000000\"").is_none());
+        // A key that isn't in the palette
+        assert!(decode_xpm("\"1 1 1 1\", \"a c #000000\", \"b\"").is_none());
+        assert!(decode_xpm("\"1 1").is_none());
+    }
+
+    fn install_theme(base: &Path, name: &str, inherits: &str) {
+        let dir = base.join(name);
+        fs::create_dir_all(dir.join("apps")).unwrap();
+        let index = format!("[Icon Theme]\nName={}\nInherits={}\nDirectories=apps\n\n[apps]\nSize=48\nType=Scalable\n", name, inherits);
+        fs::write(dir.join("index.theme"), index).unwrap();
+    }
+
+    fn theme_names(loader: &IconLoader) -> Vec<&str> {
+        loader.themes.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn configured_icon_theme_wins_over_gtk() {
+        let base = temp_dir("theme-override");
+        install_theme(&base, "Configured", "Parent");
+        install_theme(&base, "Parent", "hicolor");
+        install_theme(&base, "Gtk", "hicolor");
+        install_theme(&base, "hicolor", "");
+
+        let loader = IconLoader::with_theme_dirs(vec![base.clone()], Some("Configured"), || panic!("GTK asked"), None);
+        assert_eq!(theme_names(&loader), ["Configured", "Parent", "hicolor"]);
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn missing_icon_theme_falls_back_to_gtk() {
+        let base = temp_dir("theme-fallback");
+        install_theme(&base, "Gtk", "hicolor");
+        install_theme(&base, "hicolor", "");
+        // A directory without an index.theme isn't an installed theme
+        fs::create_dir_all(base.join("Stray")).unwrap();
+
+        for configured in [Some("Missing"), Some("Stray"), None] {
+            let loader = IconLoader::with_theme_dirs(vec![base.clone()], configured, || Some("Gtk".to_string()), None);
+            assert_eq!(theme_names(&loader), ["Gtk", "hicolor"]);
+        }
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn scaled_icons_are_premultiplied_without_dark_fringes() {
+        // Opaque white next to transparent black, the usual icon edge
+        let mut rgba = image::RgbaImage::new(4, 4);
+        for (x, _, pixel) in rgba.enumerate_pixels_mut() {
+            *pixel = if x < 2 { image::Rgba([255, 255, 255, 255]) } else { image::Rgba([0, 0, 0, 0]) };
+        }
+        let pixmap = scale_raster(rgba, 3).unwrap();
+        for pixel in pixmap.pixels() {
+            let alpha = pixel.alpha();
+            assert!(pixel.red() <= alpha && pixel.green() <= alpha && pixel.blue() <= alpha);
+            // Unpremultiplied the edge is still white, not grey
+            if alpha > 32 {
+                assert!(pixel.red() as u32 * 255 / alpha as u32 >= 250, "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn undecodable_rasters_load_as_none() {
+        let dir = temp_dir("broken");
+        let broken = dir.join("broken.png");
+        fs::write(&broken, b"\x89PNG\r\n\x1a\n truncated").unwrap();
+        assert!(bare_loader().load_from_path(&broken, 16).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
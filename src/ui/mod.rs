@@ -1,3 +1,5 @@
 pub mod wayland;
 pub mod render;
 pub mod icons;
+pub mod toplevels;
+pub mod surface;
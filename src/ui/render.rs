@@ -1,13 +1,168 @@
-use tiny_skia::{Paint, Color, Rect, Transform, PixmapMut, PixmapPaint, PathBuilder, Stroke};
-use cosmic_text::{Attrs, Buffer, FontSystem, Metrics, SwashCache};
-use crate::state::AppState;
+use tiny_skia::{Paint, Color, IntRect, Mask, Rect, Transform, Pixmap, PixmapMut, PixmapPaint, PathBuilder, Stroke};
+use cosmic_text::{Align, Attrs, Buffer, FontSystem, Metrics, SwashCache, Wrap};
+use std::borrow::Cow;
+use crate::state::{AppState, Row};
 use crate::ui::icons::IconCache;
-use crate::config::ThemeConfig;
+use crate::config::{Ellipsize, ThemeConfig};
+use crate::model::{Entry, EntryType};
+
+enum IconSlot {
+    Ready(Pixmap),
+    Pending,
+    Missing,
+}
+
+/// Height of one result row
+const ITEM_HEIGHT: f32 = 30.0;
+
+/// Height of a row with `ellipsize = "wrap"`, which fits two lines of name
+const WRAPPED_ITEM_HEIGHT: f32 = 46.0;
+
+/// Line height of wrapped names
+const WRAPPED_LINE_HEIGHT: f32 = 19.0;
+
+/// Height of the `show_detail` strip below the list
+const DETAIL_HEIGHT: f32 = 22.0;
+
+/// Marks where a too-long name was cut
+const ELLIPSIS: &str = "…";
+
+/// Height of one result row with `theme`'s `ellipsize`
+pub fn item_height(theme: &ThemeConfig) -> f32 {
+    match theme.ellipsize {
+        Ellipsize::Wrap => WRAPPED_ITEM_HEIGHT,
+        Ellipsize::End | Ellipsize::None => ITEM_HEIGHT,
+    }
+}
+
+/// Size of the icons in result rows
+const ICON_SIZE: u32 = 22;
+
+/// Y coordinate where the result list starts, below the search line
+pub fn list_start_y(theme: &ThemeConfig) -> f32 {
+    theme.padding + 20.0 + theme.spacing
+}
+
+/// Space below the list: the bottom padding, and the `show_detail` strip
+pub fn list_bottom(theme: &ThemeConfig) -> f32 {
+    theme.padding + if theme.show_detail { DETAIL_HEIGHT } else { 0.0 }
+}
+
+/// Surface height that fits `results` rows, capped at `max_height`. Always
+/// leaves room for one row so `no_results_text` still shows.
+pub fn fitted_height(theme: &ThemeConfig, results: usize, max_height: u32) -> u32 {
+    let list_start = list_start_y(theme);
+    let max_rows = ((max_height as f32 - list_start - list_bottom(theme)) / item_height(theme)).max(1.0) as usize;
+    let rows = results.clamp(1, max_rows);
+    ((list_start + rows as f32 * item_height(theme) + list_bottom(theme)).ceil() as u32).min(max_height)
+}
+
+/// Everything outside the rows a frame depends on. If any of it changed
+/// since the last frame, the whole frame is redrawn.
+#[derive(PartialEq)]
+struct FrameKey {
+    size: (u32, u32),
+    /// Bumped by every re-filter, which covers new entries and config reloads
+    generation: u64,
+    query: String,
+    query_selected: bool,
+    error: Option<String>,
+    /// Shown by `show_count`
+    counts: (usize, usize),
+    /// Entries plus section headers, which the scrollbar is sized by
+    row_count: usize,
+    scroll_offset: usize,
+}
+
+/// What a row showed. Rows whose key is unchanged aren't redrawn.
+#[derive(PartialEq)]
+struct RowKey {
+    row: Row,
+    /// A section header's label, which can change without the row moving
+    header: Option<String>,
+    entry_idx: usize,
+    selected: bool,
+    hovered: bool,
+    marked: bool,
+    icon: std::mem::Discriminant<IconSlot>,
+}
+
+/// The area row `relative_index` of the list covers, which is also its
+/// selection highlight.
+fn row_bounds(theme: &ThemeConfig, width: f32, relative_index: usize) -> Rect {
+    let y = list_start_y(theme) + relative_index as f32 * item_height(theme);
+    Rect::from_xywh(theme.padding / 2.0, y, width - theme.padding, item_height(theme)).unwrap()
+}
+
+/// The search line, down to the list at most
+fn search_bounds(theme: &ThemeConfig, width: f32) -> Rect {
+    let top = (theme.padding - 4.0).max(0.0);
+    let bottom = (theme.padding + 24.0).min(list_start_y(theme));
+    Rect::from_ltrb(theme.padding / 2.0, top, width - theme.padding / 2.0, bottom).unwrap()
+}
+
+/// The `show_detail` strip, between the list and the bottom padding
+fn detail_bounds(theme: &ThemeConfig, width: f32, height: f32) -> Rect {
+    let y = height - theme.padding - DETAIL_HEIGHT;
+    Rect::from_xywh(theme.padding / 2.0, y, width - theme.padding, DETAIL_HEIGHT).unwrap()
+}
+
+/// What the `show_detail` strip says about the selected entry, if shown
+fn detail_text(state: &AppState) -> Option<String> {
+    if !state.config.theme.show_detail {
+        return None;
+    }
+    let &entry_idx = state.filtered_indices.get(state.selected_index)?;
+    let entry = state.entry(entry_idx);
+    Some(format!("{}  {}", entry.entry_type.name(), entry.command).trim_end().to_string())
+}
+
+/// Icons to try for `entry`, best first: its own, the configured fallback,
+/// then a generic one for its type.
+fn icon_candidates<'a>(entry: &'a Entry, theme: &'a ThemeConfig) -> impl Iterator<Item = &'a str> {
+    [entry.icon.as_deref(), theme.fallback_icon.as_deref(), Some(entry.entry_type.fallback_icon())]
+        .into_iter()
+        .flatten()
+}
+
+fn rounded_rect_path(rect: Rect, radius: f32) -> Option<tiny_skia::Path> {
+    let mut pb = PathBuilder::new();
+    let x = rect.left();
+    let y = rect.top();
+    let w = rect.width();
+    let h = rect.height();
+
+    pb.move_to(x + radius, y);
+    pb.line_to(x + w - radius, y);
+    pb.quad_to(x + w, y, x + w, y + radius);
+    pb.line_to(x + w, y + h - radius);
+    pb.quad_to(x + w, y + h, x + w - radius, y + h);
+    pb.line_to(x + radius, y + h);
+    pb.quad_to(x, y + h, x, y + h - radius);
+    pb.line_to(x, y + radius);
+    pb.quad_to(x, y, x + radius, y);
+    pb.close();
+    pb.finish()
+}
 
 pub struct Renderer {
     font_system: FontSystem,
     swash_cache: SwashCache,
     pub icon_cache: IconCache,
+    /// The last frame, to redraw only the rows that changed
+    frame: Option<Pixmap>,
+    frame_key: Option<FrameKey>,
+    row_keys: Vec<RowKey>,
+    /// Vertical pixel range text is limited to while redrawing a single row
+    text_clip: Option<(i32, i32)>,
+    /// The window outline mask, with the size and radius (as bits) it is for
+    clip: Option<((u32, u32, u32), Mask)>,
+    /// What the detail strip showed
+    detail: Option<String>,
+    /// Whether the cursor is in the on phase of its blink
+    pub cursor_on: bool,
+    /// Whether the last frame had the cursor drawn
+    cursor_drawn: bool,
 }
 
 impl Renderer {
@@ -16,6 +171,14 @@ impl Renderer {
             font_system: FontSystem::new(),
             swash_cache: SwashCache::new(),
             icon_cache,
+            frame: None,
+            frame_key: None,
+            row_keys: Vec::new(),
+            text_clip: None,
+            clip: None,
+            detail: None,
+            cursor_on: true,
+            cursor_drawn: true,
         }
     }
 
@@ -23,13 +186,148 @@ impl Renderer {
         self.icon_cache.insert(name, pixmap);
     }
 
-    pub fn draw(&mut self, pixmap: &mut PixmapMut, state: &AppState) {
+    /// Render `state` into `canvas` (premultiplied RGBA, `width` x `height`)
+    /// and return the regions that changed since the last call. When only
+    /// some rows changed (the selection moved, an icon arrived) just those
+    /// are redrawn; anything else redraws the whole frame.
+    pub fn draw(&mut self, canvas: &mut [u8], width: u32, height: u32, state: &AppState) -> Vec<IntRect> {
+        let (scroll_offset, visible_items) = state.viewport(height as f32);
+        let key = FrameKey {
+            size: (width, height),
+            generation: state.generation(),
+            query: state.query.clone(),
+            query_selected: state.query_selected,
+            error: state.error.clone(),
+            counts: (state.filtered_indices.len(), state.entries.len()),
+            row_count: state.row_count(),
+            scroll_offset,
+        };
+        let theme = &state.config.theme;
+        let rows: Vec<RowKey> = (scroll_offset..state.row_count())
+            .take(visible_items)
+            .map(|row| match state.row(row) {
+                Row::Header(section) => RowKey {
+                    row: state.row(row),
+                    header: Some(state.section_label(section).to_string()),
+                    entry_idx: usize::MAX,
+                    selected: false,
+                    hovered: false,
+                    marked: false,
+                    icon: std::mem::discriminant(&IconSlot::Missing),
+                },
+                Row::Entry(i) => {
+                    let entry_idx = state.filtered_indices[i];
+                    RowKey {
+                        row: Row::Entry(i),
+                        header: None,
+                        entry_idx,
+                        selected: i == state.selected_index,
+                        hovered: state.hover_index == Some(i),
+                        marked: state.marked.contains(&entry_idx),
+                        icon: std::mem::discriminant(&self.entry_icon(state.entry(entry_idx), theme, ICON_SIZE)),
+                    }
+                }
+            })
+            .collect();
+
+        let mut frame = match self.frame.take() {
+            Some(frame) if frame.width() == width && frame.height() == height => frame,
+            _ => match Pixmap::new(width, height) {
+                Some(frame) => frame,
+                None => return Vec::new(),
+            },
+        };
+
+        let detail = detail_text(state);
+        let clip = self.take_clip(width, height, theme.border_radius);
+        let damage = if self.frame_key.as_ref() == Some(&key) && self.row_keys.len() == rows.len() {
+            let changed: Vec<usize> = rows.iter().zip(&self.row_keys)
+                .enumerate()
+                .filter(|(_, (new, old))| new != old)
+                .map(|(row, _)| row)
+                .collect();
+            let mut damage = Vec::new();
+            for row in changed {
+                let bounds = row_bounds(theme, width as f32, row);
+                self.redraw_row(&mut frame.as_mut(), state, rows[row].row, row, bounds, clip.as_ref());
+                damage.extend(bounds.round_out());
+            }
+            if self.cursor_on != self.cursor_drawn && theme.cursor_color.is_some() {
+                let bounds = search_bounds(theme, width as f32);
+                self.redraw_search_line(&mut frame.as_mut(), state, bounds, clip.as_ref());
+                damage.extend(bounds.round_out());
+            }
+            if detail != self.detail
+                && let Some(text) = &detail {
+                let bounds = detail_bounds(theme, width as f32, height as f32);
+                self.redraw_detail(&mut frame.as_mut(), theme, text, bounds);
+                damage.extend(bounds.round_out());
+            }
+            damage
+        } else {
+            self.draw_frame(&mut frame.as_mut(), state, clip.as_ref());
+            IntRect::from_xywh(0, 0, width, height).into_iter().collect()
+        };
+        self.clip = clip.map(|mask| ((width, height, theme.border_radius.to_bits()), mask));
+
+        canvas.copy_from_slice(frame.data());
+        self.frame = Some(frame);
+        self.frame_key = Some(key);
+        self.row_keys = rows;
+        self.detail = detail;
+        self.cursor_drawn = self.cursor_on;
+        self.prefetch_icons(state, scroll_offset + visible_items, visible_items);
+        self.icon_cache.cancel_stale();
+        damage
+    }
+
+    /// Start loading the icons of the `count` rows from `first_row`, e.g.
+    /// the page below the visible one, so they're ready when scrolled to.
+    /// Skipped while a filter is running, as the list is about to change.
+    fn prefetch_icons(&mut self, state: &AppState, first_row: usize, count: usize) {
+        if state.is_filter_pending() {
+            return;
+        }
+        let theme = &state.config.theme;
+        for row in (first_row..state.row_count()).take(count) {
+            let Row::Entry(i) = state.row(row) else { continue };
+            let entry = state.entry(state.filtered_indices[i]);
+            if entry.entry_type == EntryType::Line {
+                continue;
+            }
+            for name in icon_candidates(entry, theme) {
+                if self.icon_cache.prefetch(name, ICON_SIZE) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Forget the last frame so the next `draw` repaints everything, e.g.
+    /// for a new surface that has no content yet.
+    pub fn invalidate(&mut self) {
+        self.frame_key = None;
+    }
+
+    /// The window's rounded outline as a mask for the list, so highlights and
+    /// icons in the rows can't spill into its corners. Built once per size
+    /// and radius; hand it back by putting it in `self.clip`.
+    fn take_clip(&mut self, width: u32, height: u32, radius: f32) -> Option<Mask> {
+        let key = (width, height, radius.to_bits());
+        if let Some((cached_key, mask)) = self.clip.take()
+            && cached_key == key {
+            return Some(mask);
+        }
+        let mut mask = Mask::new(width, height)?;
+        let path = rounded_rect_path(Rect::from_xywh(0.0, 0.0, width as f32, height as f32)?, radius)?;
+        mask.fill_path(&path, tiny_skia::FillRule::Winding, true, Transform::identity());
+        Some(mask)
+    }
+
+    fn draw_frame(&mut self, pixmap: &mut PixmapMut, state: &AppState, clip: Option<&Mask>) {
         let theme = &state.config.theme;
         let bg_color = ThemeConfig::parse_color(&theme.background);
         let border_color = ThemeConfig::parse_color(&theme.border_color);
-        let text_color = ThemeConfig::parse_color(&theme.text);
-        let sel_bg_color = ThemeConfig::parse_color(&theme.selection_background);
-        let sel_text_color = ThemeConfig::parse_color(&theme.selection_text);
 
         pixmap.fill(Color::TRANSPARENT);
 
@@ -37,142 +335,484 @@ impl Renderer {
         let height = pixmap.height() as f32;
 
         let rect = Rect::from_xywh(0.0, 0.0, width, height).unwrap();
-        self.draw_rounded_rect(pixmap, rect, theme.border_radius, bg_color, Some(border_color));
+        self.draw_rounded_rect(pixmap, rect, theme.border_radius, bg_color, Some(border_color), None);
+
+        self.draw_search_line(pixmap, state);
+
+        let item_height = item_height(theme);
+        let list_start_y = list_start_y(theme);
+        
+        let (scroll_offset, visible_items) = state.viewport(height);
+        let total_items = state.row_count();
+
+        for row in (scroll_offset..total_items).take(visible_items) {
+            match state.row(row) {
+                Row::Header(section) => self.draw_header(pixmap, state, section, row - scroll_offset),
+                Row::Entry(i) => self.draw_row(pixmap, state, i, row - scroll_offset, clip),
+            }
+        }
+
+        // Scrollbar in the right-hand padding, only when the list overflows
+        if total_items > visible_items && visible_items > 0 {
+            let bar_width = 4.0;
+            // Centred in the right-hand padding, but never past the surface edge
+            let bar_x = (width - (theme.padding + bar_width) / 2.0).min(width - bar_width).max(0.0);
+            let track_height = visible_items as f32 * item_height;
+            let thumb_height = (track_height * visible_items as f32 / total_items as f32).max(bar_width * 2.0);
+            let max_offset = (total_items - visible_items) as f32;
+            let thumb_y = list_start_y + (track_height - thumb_height) * (scroll_offset as f32 / max_offset);
+
+            let track_color = ThemeConfig::parse_color(&theme.scrollbar_track);
+            let thumb_color = ThemeConfig::parse_color(&theme.scrollbar_thumb);
+            if let Some(track) = Rect::from_xywh(bar_x, list_start_y, bar_width, track_height) {
+                self.draw_rounded_rect(pixmap, track, bar_width / 2.0, track_color, None, None);
+            }
+            if let Some(thumb) = Rect::from_xywh(bar_x, thumb_y, bar_width, thumb_height) {
+                self.draw_rounded_rect(pixmap, thumb, bar_width / 2.0, thumb_color, None, None);
+            }
+        }
+
+        if state.filtered_indices.is_empty() && !theme.no_results_text.is_empty() {
+            let no_results_color = ThemeConfig::parse_color(&theme.no_results_color);
+            self.draw_text(pixmap, &theme.no_results_text, theme.padding, list_start_y, 16.0, no_results_color);
+        }
 
+        if let Some(text) = detail_text(state) {
+            self.draw_detail(pixmap, theme, &text, detail_bounds(theme, width, height));
+        }
+
+        if let Some(error) = &state.error {
+            // Over the bottom padding, below the last row
+            let error_y = height - theme.padding / 2.0 - 14.0;
+            self.draw_text(pixmap, error, theme.padding, error_y, 14.0, Color::from_rgba8(220, 90, 90, 255));
+        }
+    }
+
+    /// Repaint one row of an already drawn frame: reset it to the window
+    /// background, then draw it with text kept inside its bounds.
+    fn redraw_row(&mut self, pixmap: &mut PixmapMut, state: &AppState, row: Row, relative_index: usize, bounds: Rect, clip: Option<&Mask>) {
+        let mut paint = Paint::default();
+        paint.set_color(ThemeConfig::parse_color(&state.config.theme.background));
+        paint.blend_mode = tiny_skia::BlendMode::Source;
+        pixmap.fill_rect(bounds, &paint, Transform::identity(), clip);
+
+        self.text_clip = Some((bounds.top() as i32, bounds.bottom().ceil() as i32));
+        match row {
+            Row::Header(section) => self.draw_header(pixmap, state, section, relative_index),
+            Row::Entry(i) => self.draw_row(pixmap, state, i, relative_index, clip),
+        }
+        self.text_clip = None;
+    }
+
+    /// The prompt and query (or the placeholder), the cursor and the counter.
+    fn draw_search_line(&mut self, pixmap: &mut PixmapMut, state: &AppState) {
+        let theme = &state.config.theme;
+        let width = pixmap.width() as f32;
+        let text_color = ThemeConfig::parse_color(&theme.text);
         let search_y = theme.padding;
-        let search_text = if state.query.is_empty() {
-            "Search apps...".to_string()
-        } else {
-            format!("> {}", state.query)
-        };
-        let search_color = if state.query.is_empty() {
-            Color::from_rgba8(100, 100, 100, 255)
+
+        let cursor_x = if state.query.is_empty() {
+            self.draw_text(pixmap, state.placeholder(), theme.padding, search_y, 20.0, Color::from_rgba8(100, 100, 100, 255));
+            // Just before the placeholder, where typing starts
+            theme.padding - 3.0
         } else {
-            text_color
+            let prompt_color = theme.prompt_color.as_deref().map_or(text_color, ThemeConfig::parse_color);
+            let mut query_color = theme.query_color.as_deref().map_or(text_color, ThemeConfig::parse_color);
+            let prompt_width = self.draw_line(pixmap, &format!("{} ", state.prompt()), theme.padding, search_y, 20.0, prompt_color);
+            let query_x = theme.padding + prompt_width;
+            // A filled-in query is selected until the first edit
+            if state.query_selected
+                && let Some(highlight) = Rect::from_xywh(query_x, search_y - 2.0, self.measure_text(&state.query, 20.0), 24.0) {
+                let mut paint = Paint::default();
+                paint.set_color(ThemeConfig::parse_color(&theme.selection_background));
+                pixmap.fill_rect(highlight, &paint, Transform::identity(), None);
+                query_color = ThemeConfig::parse_color(&theme.selection_text);
+            }
+            query_x + self.draw_line(pixmap, &state.query, query_x, search_y, 20.0, query_color) + 1.0
         };
 
-        self.draw_text(pixmap, &search_text, theme.padding, search_y, 20.0, search_color);
+        if let Some(cursor_color) = &theme.cursor_color
+            && self.cursor_on
+            && let Some(cursor) = Rect::from_xywh(cursor_x, search_y, 2.0, 20.0) {
+            let mut paint = Paint::default();
+            paint.set_color(ThemeConfig::parse_color(cursor_color));
+            pixmap.fill_rect(cursor, &paint, Transform::identity(), None);
+        }
+
+        if theme.show_count {
+            let count_text = format!("{}/{}", state.filtered_indices.len(), state.entries.len());
+            let count_width = self.measure_text(&count_text, 14.0);
+            let count_color = ThemeConfig::parse_color(&theme.count_color);
+            self.draw_text(pixmap, &count_text, width - theme.padding - count_width, search_y + 3.0, 14.0, count_color);
+        }
+    }
+
+    /// Repaint the search line, as `redraw_row` does a row, for the cursor
+    /// blinking.
+    fn redraw_search_line(&mut self, pixmap: &mut PixmapMut, state: &AppState, bounds: Rect, clip: Option<&Mask>) {
+        let mut paint = Paint::default();
+        paint.set_color(ThemeConfig::parse_color(&state.config.theme.background));
+        paint.blend_mode = tiny_skia::BlendMode::Source;
+        pixmap.fill_rect(bounds, &paint, Transform::identity(), clip);
+
+        self.text_clip = Some((bounds.top() as i32, bounds.bottom().ceil() as i32));
+        self.draw_search_line(pixmap, state);
+        self.text_clip = None;
+    }
+
+    /// Repaint the detail strip with `text`, as `redraw_row` does a row.
+    fn redraw_detail(&mut self, pixmap: &mut PixmapMut, theme: &ThemeConfig, text: &str, bounds: Rect) {
+        let mut paint = Paint::default();
+        paint.set_color(ThemeConfig::parse_color(&theme.background));
+        paint.blend_mode = tiny_skia::BlendMode::Source;
+        pixmap.fill_rect(bounds, &paint, Transform::identity(), None);
+
+        self.text_clip = Some((bounds.top() as i32, bounds.bottom().ceil() as i32));
+        self.draw_detail(pixmap, theme, text, bounds);
+        self.text_clip = None;
+    }
+
+    /// The selected entry's type and command, cut to fit on one line.
+    fn draw_detail(&mut self, pixmap: &mut PixmapMut, theme: &ThemeConfig, text: &str, bounds: Rect) {
+        let text = self.ellipsize(text, 13.0, bounds.width() - theme.padding);
+        let color = ThemeConfig::parse_color(&theme.number_color);
+        self.draw_line(pixmap, &text, theme.padding, bounds.top() + (DETAIL_HEIGHT - 13.0) / 2.0, 13.0, color);
+    }
+
+    /// Draw the header of `grouped` section `section` as row `relative_index`.
+    fn draw_header(&mut self, pixmap: &mut PixmapMut, state: &AppState, section: usize, relative_index: usize) {
+        let theme = &state.config.theme;
+        let row_rect = row_bounds(theme, pixmap.width() as f32, relative_index);
+        let label = state.section_label(section).to_uppercase();
+        let label = self.ellipsize(&label, 12.0, row_rect.width() - theme.padding);
+        let color = ThemeConfig::parse_color(&theme.number_color);
+        // Sits low in its row, closer to the entries it heads
+        let y = row_rect.bottom() - 12.0 - 6.0;
+        self.draw_line(pixmap, &label, theme.padding, y, 12.0, color);
+    }
+
+    /// Draw row `relative_index` of the list, showing `filtered_indices[i]`.
+    /// Highlights and icons are clipped to `clip`.
+    fn draw_row(&mut self, pixmap: &mut PixmapMut, state: &AppState, i: usize, relative_index: usize, clip: Option<&Mask>) {
+        let theme = &state.config.theme;
+        let text_color = ThemeConfig::parse_color(&theme.text);
+        let sel_bg_color = ThemeConfig::parse_color(&theme.selection_background);
+        let sel_text_color = ThemeConfig::parse_color(&theme.selection_text);
+        let marked_bg_color = ThemeConfig::parse_color(&theme.marked_background);
+        let hover_bg_color = ThemeConfig::parse_color(&theme.hover_background);
+        let placeholder_color = ThemeConfig::parse_color(&theme.icon_placeholder_color);
+        let width = pixmap.width() as f32;
+        let item_height = item_height(theme);
 
-        let item_height = 30.0; 
-        let list_start_y = search_y + 20.0 + theme.spacing;
+        let entry_idx = state.filtered_indices[i];
+        let entry = state.entry(entry_idx);
+        let row_rect = row_bounds(theme, width, relative_index);
+        let y = row_rect.top();
         
-        let visible_items = (height - list_start_y - theme.padding) / item_height;
-        let visible_items = visible_items as usize;
+        let mut current_text_color = text_color;
+        let marked = state.marked.contains(&entry_idx);
+
+        if marked && i != state.selected_index {
+            self.draw_rounded_rect(pixmap, row_rect, theme.border_radius / 2.0, marked_bg_color, None, clip);
+        } else if state.hover_index == Some(i) && i != state.selected_index {
+            self.draw_rounded_rect(pixmap, row_rect, theme.border_radius / 2.0, hover_bg_color, None, clip);
+        }
+        if i == state.selected_index {
+            self.draw_rounded_rect(pixmap, row_rect, theme.border_radius / 2.0, sel_bg_color, None, clip);
+            current_text_color = sel_text_color;
+        }
         
-        let total_items = state.filtered_indices.len();
-        let scroll_offset = if total_items <= visible_items {
-            0
-        } else {
-             if state.selected_index < visible_items / 2 {
-                 0
-             } else if state.selected_index >= total_items - visible_items / 2 {
-                 total_items.saturating_sub(visible_items)
-             } else {
-                 state.selected_index - visible_items / 2
-             }
-        };
+        let mut text_x = theme.padding;
+        if let Some(slot) = state.quick_select_slot_of(i, pixmap.height() as f32) {
+            // The tenth row is on the 0 key
+            let nr_text = format!("{}. ", (slot + 1) % 10);
+            let num_color = ThemeConfig::parse_color(&theme.number_color);
+            self.draw_text(pixmap, &nr_text, theme.padding, y + (item_height - 16.0) / 2.0, 14.0, num_color);
+            text_x += 20.0;
+        }
 
-        for (i, &entry_idx) in state.filtered_indices.iter().enumerate().skip(scroll_offset).take(visible_items) {
-            let entry = &state.entries[entry_idx];
-            let relative_index = i - scroll_offset;
-            let y = list_start_y + (relative_index as f32 * item_height);
-            
-            let mut current_text_color = text_color;
-
-            if i == state.selected_index {
-                let sel_rect = Rect::from_xywh(theme.padding / 2.0, y, width - theme.padding, item_height).unwrap();
-                self.draw_rounded_rect(pixmap, sel_rect, theme.border_radius / 2.0, sel_bg_color, None);
-                current_text_color = sel_text_color;
-            }
-            
-            let mut text_x = theme.padding;
-            if relative_index < 9 {
-                let nr_text = format!("{}. ", relative_index + 1);
-                let num_color = ThemeConfig::parse_color(&theme.number_color);
-                self.draw_text(pixmap, &nr_text, theme.padding, y + (item_height - 16.0) / 2.0, 14.0, num_color);
-                text_x += 20.0;
-            }
-
-            let icon_size = 22;
-            let icon_padding = 10.0;
-            
-            if let Some(icon_name) = &entry.icon {
-                if let Some(icon_pixmap) = self.icon_cache.get(icon_name, icon_size) {
-                    let icon_paint = PixmapPaint::default();
-                    pixmap.draw_pixmap(text_x as i32, (y + (item_height - icon_size as f32) / 2.0) as i32, icon_pixmap.as_ref(), &icon_paint, Transform::identity(), None);
-                    text_x += icon_size as f32 + icon_padding;
+        let icon_size = ICON_SIZE;
+        let icon_padding = 10.0;
+        
+        let icon_y = y + (item_height - icon_size as f32) / 2.0;
+        match self.entry_icon(entry, theme, icon_size) {
+            IconSlot::Ready(icon_pixmap) => {
+                let icon_paint = PixmapPaint::default();
+                pixmap.draw_pixmap(text_x as i32, icon_y as i32, icon_pixmap.as_ref(), &icon_paint, Transform::identity(), clip);
+                text_x += icon_size as f32 + icon_padding;
+            }
+            IconSlot::Pending => {
+                // Hold the icon's place so the name doesn't shift when it arrives
+                if let Some(slot) = Rect::from_xywh(text_x, icon_y, icon_size as f32, icon_size as f32) {
+                    self.draw_rounded_rect(pixmap, slot, 4.0, placeholder_color, None, clip);
                 }
+                text_x += icon_size as f32 + icon_padding;
+            }
+            IconSlot::Missing => {}
+        }
+
+        if let Some(badge_color) = theme.type_color(&entry.entry_type) {
+            let label = entry.entry_type.badge();
+            let badge_width = self.measure_text(label, 11.0) + 8.0;
+            if let Some(badge) = Rect::from_xywh(text_x, y + (item_height - 16.0) / 2.0, badge_width, 16.0) {
+                self.draw_rounded_rect(pixmap, badge, 4.0, Color::TRANSPARENT, Some(badge_color), clip);
+                self.draw_line(pixmap, label, text_x + 4.0, badge.top() + 2.5, 11.0, badge_color);
+            }
+            text_x += badge_width + 6.0;
+        }
+
+        // Text stays clear of the check mark; the scrollbar is in the padding
+        let mut text_right = width - theme.padding;
+        if marked {
+            let check_x = text_right - self.measure_text("✓", 16.0);
+            self.draw_text(pixmap, "✓", check_x, y + (item_height - 16.0) / 2.0, 16.0, current_text_color);
+            text_right = check_x - 10.0;
+        }
+
+        // Where the name's (last) line ends, for the description to follow
+        let (name_end, line_y) = match theme.ellipsize {
+            Ellipsize::End => {
+                let line_y = y + (item_height - 16.0) / 2.0;
+                let name = self.ellipsize(&entry.name, 16.0, text_right - text_x);
+                (text_x + self.draw_line(pixmap, &name, text_x, line_y, 16.0, current_text_color), line_y)
+            }
+            Ellipsize::Wrap => self.draw_wrapped(pixmap, &entry.name, text_x, y, text_right - text_x, current_text_color),
+            Ellipsize::None => {
+                let line_y = y + (item_height - 16.0) / 2.0;
+                (text_x + self.draw_line(pixmap, &entry.name, text_x, line_y, 16.0, current_text_color), line_y)
             }
+        };
+        let desc_x = name_end + 10.0;
+        // Not worth showing if little more than the ellipsis would fit
+        if let Some(description) = &entry.description
+            && (theme.ellipsize == Ellipsize::None || text_right - desc_x >= 30.0) {
+            let desc_color = ThemeConfig::parse_color(&theme.number_color);
+            let description = match theme.ellipsize {
+                Ellipsize::None => Cow::Borrowed(description.as_str()),
+                Ellipsize::End | Ellipsize::Wrap => self.ellipsize(description, 14.0, text_right - desc_x),
+            };
+            self.draw_line(pixmap, &description, desc_x, line_y + 1.0, 14.0, desc_color);
+        }
+    }
 
-            self.draw_text(pixmap, &entry.name, text_x, y + (item_height - 16.0) / 2.0, 16.0, current_text_color);
+    /// Draw `name` on up to two lines of `max_width`, centered in the row
+    /// at `y`, the second line ellipsized if even that isn't enough. Returns
+    /// where the last line ends and its top.
+    fn draw_wrapped(&mut self, pixmap: &mut PixmapMut, name: &str, x: f32, y: f32, max_width: f32, color: Color) -> (f32, f32) {
+        let buffer = self.layout(name, Metrics::new(16.0, WRAPPED_LINE_HEIGHT), Some(max_width.max(1.0)), Wrap::WordOrGlyph);
+        let lines: Vec<(usize, f32)> = buffer.layout_runs()
+            .map(|run| (run.glyphs.iter().map(|glyph| glyph.start).min().unwrap_or(0), run.line_w))
+            .collect();
+        let shown = lines.len().clamp(1, 2);
+        let top = y + (WRAPPED_ITEM_HEIGHT - shown as f32 * WRAPPED_LINE_HEIGHT) / 2.0;
+        // Glyphs sit lower in a line taller than the font; match single lines
+        let offset = (WRAPPED_LINE_HEIGHT - 16.0) / 2.0;
+        self.draw_buffer(pixmap, &buffer, x, top - offset, color, 1);
+        match lines.get(1) {
+            None => (x + lines.first().map_or(0.0, |&(_, w)| w), top),
+            Some(&(second_start, _)) => {
+                let second_y = top + WRAPPED_LINE_HEIGHT;
+                let rest = self.ellipsize(name[second_start..].trim_start(), 16.0, max_width);
+                let rest_width = self.draw_line(pixmap, &rest, x, second_y, 16.0, color);
+                (x + rest_width, second_y)
+            }
         }
+    }
 
-        if state.filtered_indices.is_empty() {
-             self.draw_text(pixmap, "No results found", theme.padding, list_start_y, 16.0, Color::from_rgba8(150, 100, 100, 255));
+    /// `text` cut short with an ellipsis where it would pass `max_width`,
+    /// judged by the shaped glyphs' widths. What's kept is the start of the
+    /// text in reading order, so right-to-left names lose their left end.
+    fn ellipsize<'a>(&mut self, text: &'a str, size: f32, max_width: f32) -> Cow<'a, str> {
+        let ellipsis_width = self.measure_text(ELLIPSIS, size);
+        let buffer = self.layout(text, Metrics::new(size, size), None, Wrap::None);
+        let Some(run) = buffer.layout_runs().next() else {
+            return Cow::Borrowed(text);
+        };
+        if run.line_w <= max_width {
+            return Cow::Borrowed(text);
         }
+        let room = max_width - ellipsis_width;
+        // Glyphs are in display order, which isn't the text's order once
+        // right-to-left runs are involved
+        let mut glyphs: Vec<_> = run.glyphs.iter().collect();
+        glyphs.sort_by_key(|glyph| glyph.start);
+        let mut used = 0.0;
+        let cut = glyphs.into_iter()
+            .find(|glyph| {
+                used += glyph.w;
+                used > room
+            })
+            .map_or(text.len(), |glyph| glyph.start);
+        Cow::Owned(format!("{}{}", text[..cut].trim_end(), ELLIPSIS))
     }
 
-    fn draw_rounded_rect(&self, pixmap: &mut PixmapMut, rect: Rect, radius: f32, fill: Color, stroke: Option<Color>) {
-        let mut pb = PathBuilder::new();
-        let x = rect.left();
-        let y = rect.top();
-        let w = rect.width();
-        let h = rect.height();
+    /// The entry's own icon, else the configured fallback, else a generic one
+    /// for its type. A candidate that failed to load (cached as `None`) moves
+    /// on to the next; one still loading holds the slot.
+    fn entry_icon(&mut self, entry: &Entry, theme: &ThemeConfig, size: u32) -> IconSlot {
+        // Plain dmenu lines have no icon and shouldn't get a fallback either
+        if entry.entry_type == EntryType::Line {
+            return IconSlot::Missing;
+        }
 
-        pb.move_to(x + radius, y);
-        pb.line_to(x + w - radius, y);
-        pb.quad_to(x + w, y, x + w, y + radius);
-        pb.line_to(x + w, y + h - radius);
-        pb.quad_to(x + w, y + h, x + w - radius, y + h);
-        pb.line_to(x + radius, y + h);
-        pb.quad_to(x, y + h, x, y + h - radius);
-        pb.line_to(x, y + radius);
-        pb.quad_to(x, y, x + radius, y);
-        pb.close();
+        for name in icon_candidates(entry, theme) {
+            if let Some(pixmap) = self.icon_cache.get(name, size) {
+                return IconSlot::Ready(pixmap);
+            }
+            if self.icon_cache.is_pending(name) {
+                return IconSlot::Pending;
+            }
+        }
+        IconSlot::Missing
+    }
 
-        if let Some(path) = pb.finish() {
+    fn draw_rounded_rect(&self, pixmap: &mut PixmapMut, rect: Rect, radius: f32, fill: Color, stroke: Option<Color>, clip: Option<&Mask>) {
+        if let Some(path) = rounded_rect_path(rect, radius) {
             let mut paint = Paint::default();
             paint.set_color(fill);
             paint.anti_alias = true;
-            pixmap.fill_path(&path, &paint, tiny_skia::FillRule::Winding, Transform::identity(), None);
+            pixmap.fill_path(&path, &paint, tiny_skia::FillRule::Winding, Transform::identity(), clip);
 
             if let Some(s_color) = stroke {
                 let mut s_paint = Paint::default();
                 s_paint.set_color(s_color);
                 s_paint.anti_alias = true;
                 let stroke_obj = Stroke { width: 1.5, ..Default::default() };
-                pixmap.stroke_path(&path, &s_paint, &stroke_obj, Transform::identity(), None);
+                pixmap.stroke_path(&path, &s_paint, &stroke_obj, Transform::identity(), clip);
             }
         }
     }
 
-    fn draw_text(&mut self, pixmap: &mut PixmapMut, text: &str, x: f32, y: f32, size: f32, color: Color) {
+    fn measure_text(&mut self, text: &str, size: f32) -> f32 {
         let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(size, size));
-        buffer.set_size(&mut self.font_system, Some(pixmap.width() as f32 - x), None);
+        buffer.set_size(&mut self.font_system, None, None);
         buffer.set_text(&mut self.font_system, text, Attrs::new(), cosmic_text::Shaping::Advanced);
         buffer.shape_until_scroll(&mut self.font_system, false);
+        buffer.layout_runs().map(|run| run.line_w).fold(0.0, f32::max)
+    }
+
+    /// Draw `text`, wrapping at the window's right edge.
+    fn draw_text(&mut self, pixmap: &mut PixmapMut, text: &str, x: f32, y: f32, size: f32, color: Color) {
+        let buffer = self.layout(text, Metrics::new(size, size), Some(pixmap.width() as f32 - x), Wrap::WordOrGlyph);
+        self.draw_buffer(pixmap, &buffer, x, y, color, usize::MAX);
+    }
+
+    /// Draw `text` on one line, however long, and return its width.
+    fn draw_line(&mut self, pixmap: &mut PixmapMut, text: &str, x: f32, y: f32, size: f32, color: Color) -> f32 {
+        let buffer = self.layout(text, Metrics::new(size, size), None, Wrap::None);
+        self.draw_buffer(pixmap, &buffer, x, y, color, 1);
+        buffer.layout_runs().map(|run| run.line_w).fold(0.0, f32::max)
+    }
+
+    fn layout(&mut self, text: &str, metrics: Metrics, width: Option<f32>, wrap: Wrap) -> Buffer {
+        let mut buffer = Buffer::new(&mut self.font_system, metrics);
+        buffer.set_wrap(&mut self.font_system, wrap);
+        buffer.set_size(&mut self.font_system, width, None);
+        buffer.set_text(&mut self.font_system, text, Attrs::new(), cosmic_text::Shaping::Advanced);
+        // cosmic-text right-aligns right-to-left paragraphs within `width`,
+        // which would push Arabic or Hebrew names to the window's edge. The
+        // layout is left to right, so every line starts at `x`; bidi
+        // reordering within the line is unaffected.
+        for line in buffer.lines.iter_mut() {
+            line.set_align(Some(Align::Left));
+        }
+        buffer.shape_until_scroll(&mut self.font_system, false);
+        buffer
+    }
 
+    /// Draw the first `max_lines` lines of `buffer` with its top left at `x`, `y`.
+    fn draw_buffer(&mut self, pixmap: &mut PixmapMut, buffer: &Buffer, x: f32, y: f32, color: Color, max_lines: usize) {
         let text_color = cosmic_text::Color::rgba(
             (color.red() * 255.0) as u8,
             (color.green() * 255.0) as u8,
             (color.blue() * 255.0) as u8,
-            (color.alpha() * 255.0) as u8,
+            255,
         );
+        let opacity = color.alpha();
 
-        buffer.draw(&mut self.font_system, &mut self.swash_cache, text_color, |draw_x, draw_y, w, h, color| {
-            let draw_x = draw_x + x as i32;
-            let draw_y = draw_y + y as i32;
-            if w == 0 || h == 0 { return; }
-            if draw_x >= 0 && draw_y >= 0 && draw_x < pixmap.width() as i32 && draw_y < pixmap.height() as i32 {
-                 let paint = Paint {
-                    shader: tiny_skia::Shader::SolidColor(tiny_skia::Color::from_rgba8(color.r(), color.g(), color.b(), color.a())),
-                    ..Paint::default()
-                };
-                let rect = Rect::from_xywh(draw_x as f32, draw_y as f32, w as f32, h as f32);
-                if let Some(r) = rect {
-                    pixmap.fill_rect(r, &paint, Transform::identity(), None);
-                }
+        // Place each glyph ourselves rather than through `Buffer::draw`, which
+        // lays out at the origin so `x` could only be added as whole pixels.
+        // Passing the offset in keeps cosmic-text's subpixel x position.
+        let (width, height) = (pixmap.width() as i32, pixmap.height() as i32);
+        let (clip_top, clip_bottom) = self.text_clip.unwrap_or((0, height));
+        let data = pixmap.data_mut();
+        for run in buffer.layout_runs().take(max_lines) {
+            for glyph in run.glyphs.iter() {
+                let physical = glyph.physical((x, y + run.line_y), 1.0);
+                let glyph_color = glyph.color_opt.unwrap_or(text_color);
+                self.swash_cache.with_pixels(&mut self.font_system, physical.cache_key, glyph_color, |px, py, coverage| {
+                    let (px, py) = (physical.x + px, physical.y + py);
+                    if coverage.a() == 0 || px < 0 || py < clip_top || px >= width || py >= clip_bottom.min(height) {
+                        return;
+                    }
+                    let offset = (py * width + px) as usize * 4;
+                    blend_over(&mut data[offset..offset + 4], coverage, opacity);
+                });
+            }
+        }
+    }
+}
+
+/// Composite `color`, whose alpha is the glyph's coverage of the pixel,
+/// over a premultiplied RGBA pixel, scaled by `opacity`.
+fn blend_over(dst: &mut [u8], color: cosmic_text::Color, opacity: f32) {
+    let alpha = color.a() as f32 / 255.0 * opacity;
+    let keep = 1.0 - alpha;
+    let src = [color.r(), color.g(), color.b()];
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (s as f32 * alpha + *d as f32 * keep).round() as u8;
+    }
+    dst[3] = (255.0 * alpha + dst[3] as f32 * keep).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer() -> Renderer {
+        let (tx, _) = calloop::channel::channel();
+        Renderer::new(IconCache::new(tx, 0, None, None))
+    }
+
+    /// Shaping needs a font covering the script; without one there is
+    /// nothing to measure.
+    fn has_font_for(renderer: &mut Renderer, text: &str) -> bool {
+        renderer.measure_text(text, 16.0) > 0.0
+    }
+
+    #[test]
+    fn ellipsize_keeps_the_start_in_reading_order() {
+        let mut renderer = renderer();
+        for text in ["Firefox Web Browser", "שלום עולם ומלואו", "Firefox פיירפוקס Browser"] {
+            if !has_font_for(&mut renderer, text) {
+                continue;
             }
-        });
+            let max_width = renderer.measure_text(text, 16.0) / 2.0;
+            let cut = renderer.ellipsize(text, 16.0, max_width);
+            let kept = cut.strip_suffix(ELLIPSIS).unwrap();
+            assert!(!kept.is_empty() && text.starts_with(kept), "{:?} -> {:?}", text, cut);
+            assert!(renderer.measure_text(&cut, 16.0) <= max_width + 1.0, "{:?} -> {:?}", text, cut);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ellipsize_leaves_fitting_text_alone() {
+        let mut renderer = renderer();
+        let text = "שלום";
+        assert_eq!(renderer.ellipsize(text, 16.0, 1000.0), text);
+    }
+
+    #[test]
+    fn right_to_left_lines_start_at_the_left() {
+        let mut renderer = renderer();
+        let text = "שלום עולם";
+        if !has_font_for(&mut renderer, text) {
+            return;
+        }
+        let buffer = renderer.layout(text, Metrics::new(16.0, 16.0), Some(500.0), Wrap::None);
+        let left = buffer.layout_runs()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.x))
+            .fold(f32::MAX, f32::min);
+        assert!(left < 1.0, "starts at {}", left);
+    }
+}
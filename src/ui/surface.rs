@@ -0,0 +1,80 @@
+use smithay_client_toolkit::shell::{
+    wlr_layer::{KeyboardInteractivity, Layer, LayerShell, LayerSurface},
+    xdg::{window::{Window, WindowDecorations}, XdgShell},
+    WaylandSurface,
+};
+use wayland_client::{
+    protocol::{wl_output, wl_surface},
+    QueueHandle,
+};
+use crate::config::ThemeConfig;
+use crate::ui::wayland::WaylandApp;
+
+/// The launcher's window: an overlay on the layer shell where the compositor
+/// has one, else (or with `--windowed`) an ordinary xdg toplevel. A window
+/// is placed by the compositor, so anchor, margins and output don't apply.
+pub enum LauncherSurface {
+    Layer(LayerSurface),
+    Window(Window),
+}
+
+impl LauncherSurface {
+    /// An overlay with exclusive keyboard focus on `output`.
+    pub fn layer(layer_shell: &LayerShell, surface: wl_surface::WlSurface, output: Option<&wl_output::WlOutput>, qh: &QueueHandle<WaylandApp>) -> Self {
+        let layer_surface = layer_shell.create_layer_surface(qh, surface, Layer::Overlay, Some("runner"), output);
+        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        Self::Layer(layer_surface)
+    }
+
+    /// A toplevel window titled "runner". Its frame is asked of the
+    /// compositor; where it draws none, runner's own border stands in.
+    pub fn window(xdg_shell: &XdgShell, surface: wl_surface::WlSurface, qh: &QueueHandle<WaylandApp>) -> Self {
+        let window = xdg_shell.create_window(surface, WindowDecorations::RequestServer, qh);
+        window.set_title("runner");
+        window.set_app_id("runner");
+        Self::Window(window)
+    }
+
+    pub fn wl_surface(&self) -> &wl_surface::WlSurface {
+        match self {
+            Self::Layer(layer_surface) => layer_surface.wl_surface(),
+            Self::Window(window) => window.wl_surface(),
+        }
+    }
+
+    pub fn commit(&self) {
+        self.wl_surface().commit();
+    }
+
+    /// Apply the theme's size, and for an overlay its anchor and margins.
+    /// Percentage sizes are resolved against `output_size` (logical pixels).
+    /// Returns the requested size.
+    pub fn place(&self, theme: &ThemeConfig, output_size: Option<(i32, i32)>) -> (u32, u32) {
+        let width = theme.width.resolve(output_size.map(|(w, _)| w), 600);
+        let height = theme.height.resolve(output_size.map(|(_, h)| h), 400);
+        self.request_size(width, height);
+        if let Self::Layer(layer_surface) = self {
+            layer_surface.set_anchor(theme.anchor());
+            layer_surface.set_margin(theme.margin_top, theme.margin_right, theme.margin_bottom, theme.margin_left);
+        }
+        (width, height)
+    }
+
+    /// Ask for a new size. An overlay gets it with the next configure. A
+    /// window draws at whatever size it likes unless the compositor says
+    /// otherwise, so returns true when the caller should use it right away.
+    pub fn request_size(&self, width: u32, height: u32) -> bool {
+        match self {
+            Self::Layer(layer_surface) => {
+                layer_surface.set_size(width, height);
+                false
+            }
+            Self::Window(window) => {
+                // A fixed size also keeps tiling compositors from tiling it
+                window.set_min_size(Some((width, height)));
+                window.set_max_size(Some((width, height)));
+                true
+            }
+        }
+    }
+}
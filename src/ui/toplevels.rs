@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::path::Path;
+use wayland_client::{
+    backend::ObjectId,
+    event_created_child,
+    globals::GlobalList,
+    protocol::wl_seat,
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+use crate::model::{Entry, EntryType};
+use crate::ui::wayland::WaylandApp;
+
+/// Other apps' open windows, from the wlr foreign toplevel protocol, so a
+/// launch can raise a running app instead of starting it again.
+pub struct Toplevels {
+    /// `None` when the compositor doesn't offer the protocol
+    manager: Option<ZwlrForeignToplevelManagerV1>,
+    /// Every open toplevel with its app_id (lowercased) once known
+    windows: HashMap<ObjectId, (ZwlrForeignToplevelHandleV1, Option<String>)>,
+}
+
+impl Toplevels {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<WaylandApp>) -> Self {
+        let manager = globals.bind(qh, 1..=3, ())
+            .map_err(|e| log::debug!("Foreign toplevel management not available: {}", e))
+            .ok();
+        Self { manager, windows: HashMap::new() }
+    }
+
+    /// Focus an open window of the app `entry` launches. Returns false if
+    /// there is none (or no way to tell).
+    pub fn raise(&self, entry: &Entry, seat: &wl_seat::WlSeat) -> bool {
+        // After `finished` the handles are stale and activating them is a no-op
+        if self.manager.is_none() {
+            return false;
+        }
+        let app_ids = app_ids(entry);
+        let window = self.windows.values()
+            .find(|(_, app_id)| app_id.as_ref().is_some_and(|id| app_ids.contains(id)));
+        match window {
+            Some((handle, app_id)) => {
+                log::info!("Raising running {:?} instead of launching {}", app_id, entry.name);
+                handle.activate(seat);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The app_ids `entry`'s windows may have: its StartupWMClass, its desktop
+/// file id (e.g. "org.gnome.Nautilus") or its executable name. Only apps
+/// qualify; desktop actions such as "New Window" always launch.
+fn app_ids(entry: &Entry) -> Vec<String> {
+    if entry.entry_type != EntryType::Desktop || entry.id.contains('#') {
+        return Vec::new();
+    }
+
+    let mut ids = Vec::new();
+    ids.extend(entry.wm_class.as_deref().map(str::to_lowercase));
+    ids.extend(Path::new(&entry.id).file_stem().map(|s| s.to_string_lossy().to_lowercase()));
+    ids.extend(entry.executable_name().map(|name| name.to_lowercase()));
+    ids
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for WaylandApp {
+    fn event(
+        app: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                app.toplevels.windows.insert(toplevel.id(), (toplevel, None));
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => {
+                app.toplevels.manager = None;
+                app.toplevels.windows.clear();
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(WaylandApp, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for WaylandApp {
+    fn event(
+        app: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                if let Some((_, id)) = app.toplevels.windows.get_mut(&handle.id()) {
+                    *id = Some(app_id.to_lowercase());
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                app.toplevels.windows.remove(&handle.id());
+                handle.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desktop_entry(id: &str, command: &str) -> Entry {
+        Entry::new(id.to_string(), "App".to_string(), command.to_string(), EntryType::Desktop, false)
+    }
+
+    #[test]
+    fn app_ids_come_from_wm_class_desktop_id_and_executable() {
+        let mut entry = desktop_entry("/usr/share/applications/org.gnome.Nautilus.desktop", "/usr/bin/nautilus --new-window");
+        entry.wm_class = Some("Org.Gnome.Nautilus".to_string());
+        assert_eq!(app_ids(&entry), ["org.gnome.nautilus", "org.gnome.nautilus", "nautilus"]);
+    }
+
+    #[test]
+    fn only_apps_are_raised() {
+        assert!(app_ids(&desktop_entry("/apps/firefox.desktop#new-private", "firefox --private-window")).is_empty());
+        let binary = Entry::new("/usr/bin/foot".into(), "foot".into(), "/usr/bin/foot".into(), EntryType::Binary, false);
+        assert!(app_ids(&binary).is_empty());
+    }
+}
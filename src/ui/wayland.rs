@@ -1,30 +1,72 @@
 use smithay_client_toolkit::{
-    compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_keyboard, delegate_output, delegate_registry, delegate_seat,
-    delegate_shm, delegate_layer,
+    compositor::{CompositorHandler, CompositorState, Region},
+    delegate_compositor, delegate_keyboard, delegate_output, delegate_pointer, delegate_registry, delegate_seat,
+    delegate_shm, delegate_layer, delegate_xdg_shell, delegate_xdg_window,
     output::{OutputHandler, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     seat::{
-        keyboard::{KeyEvent, KeyboardHandler, Modifiers},
+        keyboard::{KeyEvent, KeyboardHandler, Modifiers, RepeatInfo},
+        pointer::{PointerEvent, PointerEventKind, PointerHandler},
         Capability, SeatHandler, SeatState,
     },
     shell::{
-        wlr_layer::{
-            LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
-        },
-        WaylandSurface,
+        wlr_layer::{LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
+        xdg::{window::{Window, WindowConfigure, WindowHandler}, XdgShell},
     },
-    shm::{slot::SlotPool, Shm, ShmHandler},
+    shm::{slot::{Buffer, SlotPool}, Shm, ShmHandler},
 };
 use wayland_client::{
     globals::GlobalList,
-    protocol::{wl_keyboard, wl_output, wl_seat, wl_shm, wl_surface},
+    protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
     Connection, QueueHandle,
 };
 use xkbcommon::xkb::{self, keysyms};
+use calloop::{LoopHandle, RegistrationToken, timer::{Timer, TimeoutAction}};
+use anyhow::{Context, Result, bail};
+use std::num::NonZeroU32;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::{Config, CustomSourceConfig};
+use crate::model::Entry;
 use crate::state::AppState;
-use crate::ui::render::Renderer;
+use crate::ui::render::{self, Renderer};
+use crate::ui::surface::LauncherSurface;
+use crate::ui::toplevels::Toplevels;
+use crate::sources::{custom, history};
 use crate::executor;
+use crate::timings::Timings;
+
+/// A script source's output for the next level of its menu, see
+/// `WaylandApp::open_script_level`
+struct ScriptRun {
+    run: u64,
+    source: String,
+    selections: Vec<String>,
+    entries: Result<Vec<Entry>>,
+}
+
+/// Find an output by its connector name ("DP-1") or its description.
+fn find_output(output_state: &OutputState, wanted: &str) -> Option<wl_output::WlOutput> {
+    output_state.outputs().find(|output| {
+        output_state.info(output).is_some_and(|info| {
+            info.name.as_deref() == Some(wanted) || info.description.as_deref() == Some(wanted)
+        })
+    })
+}
+
+/// Buffers kept for drawing: one on screen, one queued and one to draw into
+const MAX_BUFFERS: usize = 3;
+
+/// Interval between frames of a fade, about 60 per second
+const FADE_FRAME: Duration = Duration::from_millis(16);
+
+/// A running fade of the whole window
+struct Fade {
+    started: Instant,
+    /// Fading out, after which the launcher closes
+    out: bool,
+    timer: RegistrationToken,
+}
 
 pub struct WaylandApp {
     pub registry_state: RegistryState,
@@ -32,81 +74,600 @@ pub struct WaylandApp {
     pub output_state: OutputState,
     pub compositor_state: CompositorState,
     pub shm_state: Shm,
-    pub layer_shell_state: LayerShell,
+    /// `None` when the compositor has no layer shell (e.g. GNOME)
+    pub layer_shell_state: Option<LayerShell>,
+    pub xdg_shell_state: Option<XdgShell>,
 
-    pub layer_surface: Option<LayerSurface>,
+    pub surface: Option<LauncherSurface>,
+    /// Logical size of the output the surface is placed on, if known
+    pub output_size: Option<(i32, i32)>,
+    /// Height last asked of the compositor, which `height` lags behind
+    /// until the matching configure arrives
+    pub requested_height: Option<u32>,
     pub pool: Option<SlotPool>,
+    /// Buffers drawn into, reused once the compositor releases them
+    buffers: Vec<Buffer>,
     pub width: u32,
     pub height: u32,
     pub first_configure: bool,
     pub should_exit: bool,
+    /// Hide instead of exiting when closed, waiting for the next `--show`
+    pub daemon: bool,
+    /// Output to show on, by name or description
+    pub output_name: Option<String>,
+    /// Open a regular window even when the layer shell is available
+    pub windowed: bool,
+    /// The lines picked in dmenu mode, printed on exit
+    pub dmenu_selection: Vec<String>,
+    /// Print the selected entry's command instead of running it
+    pub print_only: bool,
+
+    pub loop_handle: LoopHandle<'static, WaylandApp>,
+    filter_timer: Option<RegistrationToken>,
+    repeat_info: RepeatInfo,
+    /// Raw keycode of the held key being repeated, and its repeat timer
+    key_repeat: Option<(u32, RegistrationToken)>,
+    /// Modifier keys currently held, for `quick_select_modifier`
+    modifiers: Modifiers,
+    /// Open windows, for `raise_if_running`
+    pub toplevels: Toplevels,
+    /// Reports the first frame to `--timings`, then cleared
+    pub paint_timings: Option<Timings>,
+    /// The `fade_ms` animation in progress
+    fade: Option<Fade>,
+    /// Size of the opaque region set on the surface, None while it has none
+    opaque_region: Option<(u32, u32)>,
+    /// Toggles the cursor every `cursor_blink_ms`
+    cursor_blink: Option<RegistrationToken>,
+    /// Where threads running a script source send the next menu level
+    script_tx: calloop::channel::Sender<ScriptRun>,
+    /// Numbers script runs, so a run picked past or backed out of is ignored
+    script_runs: u64,
+    /// The script run whose menu level is awaited
+    script_pending: Option<u64>,
+    /// For redraws from timers started where neither is at hand
+    conn: Connection,
+    qh: QueueHandle<Self>,
 
     pub state: AppState,
     pub renderer: Renderer,
 }
 
 impl WaylandApp {
-    pub fn new(_conn: &Connection, globals: &GlobalList, qh: &QueueHandle<Self>, state: AppState, renderer: Renderer) -> Self {
+    pub fn new(
+        conn: &Connection,
+        globals: &GlobalList,
+        qh: &QueueHandle<Self>,
+        loop_handle: LoopHandle<'static, WaylandApp>,
+        state: AppState,
+        renderer: Renderer,
+    ) -> Result<Self> {
         let registry_state = RegistryState::new(globals);
         let seat_state = SeatState::new(globals, qh);
         let output_state = OutputState::new(globals, qh);
-        let compositor_state = CompositorState::bind(globals, qh).expect("wl_compositor not available");
-        let shm_state = Shm::bind(globals, qh).expect("wl_shm not available");
-        let layer_shell_state = LayerShell::bind(globals, qh).expect("zwlr_layer_shell_v1 not available");
+        let compositor_state = CompositorState::bind(globals, qh)
+            .context("your compositor does not support wl_compositor")?;
+        let shm_state = Shm::bind(globals, qh)
+            .context("your compositor does not support wl_shm")?;
+        // GNOME's Mutter, for one, has no layer shell; runner then opens
+        // a regular window instead
+        let layer_shell_state = LayerShell::bind(globals, qh)
+            .map_err(|e| log::info!("Layer shell not available, using a regular window: {}", e))
+            .ok();
+        let xdg_shell_state = XdgShell::bind(globals, qh).ok();
+        if layer_shell_state.is_none() && xdg_shell_state.is_none() {
+            bail!("your compositor supports neither zwlr_layer_shell_v1 nor xdg_wm_base, so runner can't show a window");
+        }
+        let toplevels = Toplevels::bind(globals, qh);
 
-        Self {
+        let (script_tx, script_rx) = calloop::channel::channel();
+        loop_handle.insert_source(script_rx, |event, _, app: &mut WaylandApp| {
+            if let calloop::channel::Event::Msg(run) = event {
+                app.finish_script_level(run);
+            }
+        }).map_err(|e| e.error)?;
+
+        Ok(Self {
             registry_state,
             seat_state,
             output_state,
             compositor_state,
             shm_state,
             layer_shell_state,
-            layer_surface: None,
+            xdg_shell_state,
+            surface: None,
+            windowed: false,
+            output_size: None,
+            requested_height: None,
             pool: None,
+            buffers: Vec::new(),
             width: 600,
             height: 400,
             first_configure: true,
             should_exit: false,
+            daemon: false,
+            output_name: None,
+            dmenu_selection: Vec::new(),
+            print_only: false,
+            loop_handle,
+            cursor_blink: None,
+            script_tx,
+            script_runs: 0,
+            script_pending: None,
+            filter_timer: None,
+            // Common compositor defaults until the real values arrive
+            repeat_info: RepeatInfo::Repeat { rate: NonZeroU32::new(25).unwrap(), delay: 600 },
+            key_repeat: None,
+            modifiers: Modifiers::default(),
+            paint_timings: None,
+            opaque_region: None,
+            fade: None,
+            conn: conn.clone(),
+            qh: qh.clone(),
+            toplevels,
             state,
             renderer,
+        })
+    }
+
+    /// Create and map the launcher's surface, on the configured output if
+    /// it is an overlay.
+    pub fn show(&mut self, qh: &QueueHandle<Self>) {
+        if self.surface.is_some() {
+            return;
+        }
+
+        let output = self.output_name.as_deref().and_then(|wanted| {
+            let found = find_output(&self.output_state, wanted);
+            if found.is_none() {
+                log::warn!("No output named {:?}, using the compositor's default", wanted);
+            }
+            found
+        });
+
+        let wl_surface = self.compositor_state.create_surface(qh);
+        let surface = match (&self.layer_shell_state, &self.xdg_shell_state) {
+            (Some(layer_shell), _) if !self.windowed => LauncherSurface::layer(layer_shell, wl_surface, output.as_ref(), qh),
+            (_, Some(xdg_shell)) => LauncherSurface::window(xdg_shell, wl_surface, qh),
+            (Some(layer_shell), None) => {
+                log::warn!("The compositor has no xdg_wm_base for --windowed, showing an overlay");
+                LauncherSurface::layer(layer_shell, wl_surface, output.as_ref(), qh)
+            }
+            (None, None) => unreachable!("WaylandApp::new checks for a shell"),
+        };
+
+        // Percentage sizes need the output's size; without an explicit output,
+        // assume the first one
+        self.output_size = output.or_else(|| self.output_state.outputs().next())
+            .and_then(|o| self.output_state.info(&o))
+            .and_then(|info| info.logical_size);
+        let (width, height) = surface.place(&self.state.config.theme, self.output_size);
+        // A window is configured without a size when it may pick its own
+        self.width = width;
+        self.height = height;
+        self.requested_height = Some(height);
+        surface.commit();
+        self.surface = Some(surface);
+    }
+
+    /// Destroy the surface, keeping everything else loaded.
+    pub fn hide(&mut self) {
+        self.stop_key_repeat();
+        self.stop_fade();
+        self.stop_cursor_blink();
+        self.script_pending = None;
+        self.surface = None;
+        self.opaque_region = None;
+        self.first_configure = true;
+    }
+
+    /// Launch the selected entry (or print its command), or in dmenu mode
+    /// pick its line, then close. With entries marked in `--multi` mode it
+    /// acts on all of them instead. A failed launch keeps the window open
+    /// with the error shown.
+    fn launch_selected(&mut self) {
+        let picked = self.state.picked();
+        if let [entry_idx] = picked[..] {
+            let entry = self.state.entry(entry_idx);
+            if let Some(source) = self.state.config.script_source(&entry.source).cloned() {
+                let entry = entry.clone();
+                self.open_script_level(&source, &entry);
+                return;
+            }
+        }
+
+        let mut errors = Vec::new();
+        for &entry_idx in &picked {
+            let entry = self.state.entry(entry_idx);
+            let result = if self.state.dmenu {
+                self.dmenu_selection.push(entry.name.clone());
+                Ok(())
+            } else if self.print_only {
+                executor::print(entry, &self.state.config, &self.state.active_group)
+            } else if self.state.config.general.raise_if_running && self.raise_running(entry) {
+                let _ = history::increment_usage(&self.state.active_group, &entry.id, self.state.config.general.history_size);
+                Ok(())
+            } else {
+                executor::execute(entry, &self.state.config, &self.state.active_group)
+            };
+
+            if let Err(e) = result {
+                log::warn!("Failed to launch {}: {}", entry.name, e);
+                errors.push(format!("Failed to launch {}: {}", entry.name, e));
+            }
+        }
+
+        if errors.is_empty() {
+            if !picked.is_empty() {
+                self.close();
+            }
+        } else {
+            self.state.error = Some(errors.join("; "));
+        }
+    }
+
+    /// Run a script source again for the menu level below `entry`. It runs
+    /// on its own thread so a slow script doesn't freeze the window;
+    /// `finish_script_level` picks up its output.
+    fn open_script_level(&mut self, source: &CustomSourceConfig, entry: &Entry) {
+        let mut selections = self.state.script_selections().to_vec();
+        selections.push(entry.name.clone());
+
+        self.script_runs += 1;
+        self.script_pending = Some(self.script_runs);
+        let run = self.script_runs;
+        let source = source.clone();
+        let info = entry.command.clone();
+        let tx = self.script_tx.clone();
+        thread::spawn(move || {
+            let entries = custom::select(&source, &selections, &info);
+            let _ = tx.send(ScriptRun { run, source: source.name, selections, entries });
+        });
+    }
+
+    /// Open the menu level a script run printed, or close if it printed
+    /// nothing more to pick. Runs that were picked past, or whose level was
+    /// backed out of or closed meanwhile, are dropped.
+    fn finish_script_level(&mut self, run: ScriptRun) {
+        if self.script_pending != Some(run.run) {
+            log::debug!("Dropping stale output of script source {}", run.source);
+            return;
+        }
+        self.script_pending = None;
+        match run.entries {
+            Ok(entries) if entries.is_empty() => self.close(),
+            Ok(entries) => self.state.push_script_level(run.selections, entries),
+            Err(e) => {
+                log::warn!("Script source {} failed: {}", run.source, e);
+                self.state.error = Some(format!("{} failed: {}", run.source, e));
+            }
+        }
+        let (conn, qh) = (self.conn.clone(), self.qh.clone());
+        self.draw(&conn, &qh);
+    }
+
+    /// Focus an open window of `entry`'s app, if there is one.
+    fn raise_running(&self, entry: &Entry) -> bool {
+        self.seat_state.seats().next()
+            .is_some_and(|seat| self.toplevels.raise(entry, &seat))
+    }
+
+    /// Escape, a launch or losing focus: exit, or just hide in daemon mode.
+    /// With `fade_ms` the window fades out first.
+    fn close(&mut self) {
+        if self.fade.as_ref().is_some_and(|fade| fade.out) {
+            return;
+        }
+        if self.state.config.theme.fade_ms > 0 && self.surface.is_some() && !self.first_configure {
+            self.start_fade(true);
+        } else {
+            self.finish_close();
+        }
+    }
+
+    fn finish_close(&mut self) {
+        self.state.save_query();
+        if self.daemon {
+            self.hide();
+        } else {
+            self.should_exit = true;
+        }
+    }
+
+    /// Start fading the window in or out, redrawing every `FADE_FRAME`.
+    fn start_fade(&mut self, out: bool) {
+        self.stop_fade();
+        let timer = Timer::from_duration(FADE_FRAME);
+        let token = self.loop_handle.insert_source(timer, |_, _, app: &mut WaylandApp| app.fade_step());
+
+        match token {
+            Ok(timer) => self.fade = Some(Fade { started: Instant::now(), out, timer }),
+            Err(e) => {
+                log::warn!("Failed to schedule fade timer: {}", e);
+                if out {
+                    self.finish_close();
+                }
+            }
         }
     }
 
+    fn fade_step(&mut self) -> TimeoutAction {
+        let Some(fade) = &self.fade else { return TimeoutAction::Drop };
+        let out = fade.out;
+        let finished = fade.started.elapsed().as_millis() >= self.state.config.theme.fade_ms as u128;
+
+        if finished {
+            self.fade = None;
+            if out {
+                self.finish_close();
+                return TimeoutAction::Drop;
+            }
+            // Fully opaque again; the renderer doesn't know anything changed
+            self.renderer.invalidate();
+        }
+        let (conn, qh) = (self.conn.clone(), self.qh.clone());
+        self.draw(&conn, &qh);
+        if finished { TimeoutAction::Drop } else { TimeoutAction::ToDuration(FADE_FRAME) }
+    }
+
+    fn stop_fade(&mut self) {
+        if let Some(fade) = self.fade.take() {
+            self.loop_handle.remove(fade.timer);
+        }
+    }
+
+    /// How opaque the window is at this point of a fade.
+    fn fade_opacity(&self) -> Option<f32> {
+        let fade = self.fade.as_ref()?;
+        let duration = self.state.config.theme.fade_ms.max(1) as f32;
+        let progress = (fade.started.elapsed().as_millis() as f32 / duration).min(1.0);
+        Some(if fade.out { 1.0 - progress } else { progress })
+    }
+
+    /// Blink the cursor, if there is one, from the on phase.
+    fn restart_cursor_blink(&mut self) {
+        self.stop_cursor_blink();
+        let theme = &self.state.config.theme;
+        if theme.cursor_color.is_none() || theme.cursor_blink_ms == 0 {
+            return;
+        }
+
+        let interval = Duration::from_millis(theme.cursor_blink_ms);
+        let token = self.loop_handle.insert_source(Timer::from_duration(interval), move |_, _, app: &mut WaylandApp| {
+            app.renderer.cursor_on = !app.renderer.cursor_on;
+            let (conn, qh) = (app.conn.clone(), app.qh.clone());
+            app.draw(&conn, &qh);
+            TimeoutAction::ToDuration(interval)
+        });
+        match token {
+            Ok(token) => self.cursor_blink = Some(token),
+            Err(e) => log::warn!("Failed to schedule cursor blink: {}", e),
+        }
+    }
+
+    fn stop_cursor_blink(&mut self) {
+        if let Some(token) = self.cursor_blink.take() {
+            self.loop_handle.remove(token);
+        }
+        self.renderer.cursor_on = true;
+    }
+
+    /// Re-filter after the query changed. The query itself is echoed on the
+    /// next frame right away; matching is deferred until typing pauses for
+    /// `filter_debounce_ms` so bursts of keystrokes only filter once.
+    fn schedule_filter(&mut self, conn: &Connection, qh: &QueueHandle<Self>) {
+        if let Some(token) = self.filter_timer.take() {
+            self.loop_handle.remove(token);
+        }
+
+        let delay = self.state.config.general.filter_debounce_ms;
+        if delay == 0 {
+            self.state.update_filter();
+            return;
+        }
+
+        let conn = conn.clone();
+        let qh = qh.clone();
+        let timer = Timer::from_duration(Duration::from_millis(delay));
+        let token = self.loop_handle.insert_source(timer, move |_, _, app: &mut WaylandApp| {
+            app.filter_timer = None;
+            app.state.update_filter();
+            app.draw(&conn, &qh);
+            TimeoutAction::Drop
+        });
+
+        match token {
+            Ok(token) => self.filter_timer = Some(token),
+            Err(e) => {
+                log::warn!("Failed to schedule filter timer: {}", e);
+                self.state.update_filter();
+            }
+        }
+    }
+
+    /// Re-fire `event` while its key is held, after the compositor's repeat
+    /// delay and at its repeat rate, until `stop_key_repeat`.
+    fn start_key_repeat(&mut self, conn: &Connection, qh: &QueueHandle<Self>, event: KeyEvent) {
+        let RepeatInfo::Repeat { rate, delay } = self.repeat_info else { return };
+        let interval = Duration::from_micros(1_000_000 / rate.get() as u64);
+
+        let conn = conn.clone();
+        let qh = qh.clone();
+        let raw_code = event.raw_code;
+        let timer = Timer::from_duration(Duration::from_millis(delay as u64));
+        let token = self.loop_handle.insert_source(timer, move |_, _, app: &mut WaylandApp| {
+            app.handle_key(&conn, &qh, event.clone());
+            TimeoutAction::ToDuration(interval)
+        });
+
+        match token {
+            Ok(token) => self.key_repeat = Some((raw_code, token)),
+            Err(e) => log::warn!("Failed to schedule key repeat: {}", e),
+        }
+    }
+
+    fn stop_key_repeat(&mut self) {
+        if let Some((_, token)) = self.key_repeat.take() {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    /// Run a pending debounced or in-flight filter immediately, so actions
+    /// act on the results for the query as currently typed.
+    fn flush_filter(&mut self) {
+        let debounced = if let Some(token) = self.filter_timer.take() {
+            self.loop_handle.remove(token);
+            true
+        } else {
+            false
+        };
+
+        if debounced || self.state.is_filter_pending() {
+            self.state.filter_now();
+        }
+    }
+
+    /// Swap in a freshly loaded config, resizing the surface if needed.
+    pub fn apply_config(&mut self, config: Config) {
+        if let Some(surface) = &self.surface {
+            let (width, height) = surface.place(&config.theme, self.output_size);
+            if let LauncherSurface::Window(_) = surface {
+                self.width = width;
+                self.height = height;
+            }
+            self.requested_height = Some(height);
+            surface.commit();
+        }
+        self.state.set_config(config);
+        if self.surface.is_some() {
+            self.restart_cursor_blink();
+        }
+    }
+
+    /// With `dynamic_height`, resize the surface to fit the current results,
+    /// up to the configured height. The compositor is only asked when the
+    /// wanted height changes; the new size arrives through `configure`.
+    fn fit_height(&mut self) {
+        let theme = &self.state.config.theme;
+        if !theme.dynamic_height || self.first_configure || self.state.is_filter_pending() {
+            return;
+        }
+        let Some(surface) = &self.surface else { return };
+
+        let max_height = theme.height.resolve(self.output_size.map(|(_, h)| h), 400);
+        let wanted = render::fitted_height(theme, self.state.row_count(), max_height);
+        if Some(wanted) == self.requested_height {
+            return;
+        }
+        if surface.request_size(self.width, wanted) {
+            self.height = wanted;
+        }
+        surface.commit();
+        self.requested_height = Some(wanted);
+    }
+
     pub fn draw(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>) {
-        if let Some(layer_surface) = &self.layer_surface {
+        self.fit_height();
+        if let Some(launcher_surface) = &self.surface {
             let width = self.width;
             let height = self.height;
             if width == 0 || height == 0 { return; }
             
+            let opacity = self.fade_opacity();
             let Some(pool) = self.pool.as_mut() else { return; };
 
-            let (buffer, canvas) = pool
-                .create_buffer(
-                    width as i32,
-                    height as i32,
-                    (width * 4) as i32,
-                    wl_shm::Format::Argb8888,
-                )
-                .expect("create buffer");
-
-            if let Some(mut pixmap) = tiny_skia::PixmapMut::from_bytes(canvas, width, height) {
-                self.renderer.draw(&mut pixmap, &self.state);
-                
-                for chunk in canvas.chunks_exact_mut(4) {
-                    chunk.swap(0, 2);
+            // Draw into a buffer the compositor has released. While it still
+            // reads the others, a new one is made; past MAX_BUFFERS that one
+            // is only used for this frame (sctk destroys it once released).
+            let stride = width as i32 * 4;
+            self.buffers.retain(|b| b.height() == height as i32 && b.stride() == stride);
+            let spare;
+            let buffer = match self.buffers.iter().position(|b| b.canvas(pool).is_some()) {
+                Some(i) => &self.buffers[i],
+                None => {
+                    let (buffer, _) = pool
+                        .create_buffer(width as i32, height as i32, stride, wl_shm::Format::Argb8888)
+                        .expect("create buffer");
+                    if self.buffers.len() < MAX_BUFFERS {
+                        self.buffers.push(buffer);
+                        &self.buffers[self.buffers.len() - 1]
+                    } else {
+                        spare = buffer;
+                        &spare
+                    }
+                }
+            };
+            let Some(canvas) = buffer.canvas(pool) else { return; };
+
+            let started = Instant::now();
+            let mut damage = self.renderer.draw(canvas, width, height, &self.state);
+            if let Some(opacity) = opacity {
+                // Premultiplied, so every channel scales with the opacity
+                for byte in canvas.iter_mut() {
+                    *byte = (*byte as f32 * opacity).round() as u8;
                 }
-                
-                layer_surface.wl_surface().attach(Some(buffer.wl_buffer()), 0, 0);
-                layer_surface.wl_surface().damage(0, 0, width as i32, height as i32);
-                layer_surface.wl_surface().commit();
+                damage = tiny_skia::IntRect::from_xywh(0, 0, width, height).into_iter().collect();
+            }
+            // Nothing changed; the buffer stays free for the next frame
+            if damage.is_empty() {
+                return;
+            }
+            
+            to_argb8888(canvas);
+            
+            let surface = launcher_surface.wl_surface();
+            // Marks the buffer busy until the compositor releases it
+            if let Err(e) = buffer.attach_to(surface) {
+                log::warn!("Failed to attach buffer: {}", e);
+                return;
             }
+            for rect in &damage {
+                surface.damage(rect.x(), rect.y(), rect.width() as i32, rect.height() as i32);
+            }
+            // Fading makes even an opaque theme translucent
+            let opaque = (opacity.is_none() && self.state.config.theme.is_opaque()).then_some((width, height));
+            if opaque != self.opaque_region {
+                set_opaque_region(&self.compositor_state, surface, opaque);
+                self.opaque_region = opaque;
+            }
+            surface.commit();
+            if let Some(timings) = self.paint_timings.take() {
+                timings.report("first paint", started.elapsed());
+            }
+        }
+    }
+}
+
+/// Tell the compositor the surface is opaque up to `size`, so it can skip
+/// drawing what's behind, or with None that it may be translucent anywhere.
+/// Takes effect with the next commit.
+fn set_opaque_region(compositor: &CompositorState, surface: &wl_surface::WlSurface, size: Option<(u32, u32)>) {
+    let Some((width, height)) = size else {
+        surface.set_opaque_region(None);
+        return;
+    };
+    match Region::new(compositor) {
+        Ok(region) => {
+            region.add(0, 0, width as i32, height as i32);
+            surface.set_opaque_region(Some(region.wl_region()));
         }
+        Err(e) => log::warn!("Failed to create the opaque region: {}", e),
+    }
+}
+
+/// Convert tiny-skia's premultiplied RGBA bytes in place to `Argb8888`,
+/// which is also premultiplied but a native-endian `0xAARRGGBB` word per
+/// pixel (B, G, R, A in memory on little-endian machines).
+fn to_argb8888(canvas: &mut [u8]) {
+    for pixel in canvas.chunks_exact_mut(4) {
+        let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        pixel.copy_from_slice(&u32::from_be_bytes([a, r, g, b]).to_ne_bytes());
     }
 }
 
 impl LayerShellHandler for WaylandApp {
     fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.should_exit = true;
+        self.close();
     }
 
     fn configure(
@@ -117,26 +678,60 @@ impl LayerShellHandler for WaylandApp {
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        if configure.new_size.0 > 0 {
-            self.width = configure.new_size.0;
+        let (width, height) = configure.new_size;
+        self.configured(conn, qh, (width > 0).then_some(width), (height > 0).then_some(height));
+    }
+}
+
+impl WindowHandler for WaylandApp {
+    fn request_close(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _window: &Window) {
+        self.close();
+    }
+
+    fn configure(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        _window: &Window,
+        configure: WindowConfigure,
+        _serial: u32,
+    ) {
+        let (width, height) = configure.new_size;
+        self.configured(conn, qh, width.map(|w| w.get()), height.map(|h| h.get()));
+    }
+}
+
+impl WaylandApp {
+    /// The compositor set the surface's size (or left a dimension to us),
+    /// overlay or window alike. Makes sure the pool fits and repaints.
+    fn configured(&mut self, conn: &Connection, qh: &QueueHandle<Self>, width: Option<u32>, height: Option<u32>) {
+        if let Some(width) = width {
+            self.width = width;
         }
-        if configure.new_size.1 > 0 {
-            self.height = configure.new_size.1;
+        if let Some(height) = height {
+            self.height = height;
+        }
+        if self.first_configure && self.state.config.theme.fade_ms > 0 {
+            self.start_fade(false);
         }
-
         if self.first_configure {
-            self.first_configure = false;
+            self.restart_cursor_blink();
+        }
+
+        self.first_configure = false;
+        if self.pool.is_none() {
             let pool = SlotPool::new(self.width as usize * self.height as usize * 4, &self.shm_state)
                 .expect("Failed to create pool");
             self.pool = Some(pool);
         }
         
-        if let Some(pool) = &mut self.pool {
-            if pool.len() < (self.width * self.height * 4) as usize {
-                 pool.resize((self.width * self.height * 4) as usize).unwrap();
-            }
+        if let Some(pool) = &mut self.pool
+            && pool.len() < (self.width * self.height * 4) as usize {
+            pool.resize((self.width * self.height * 4) as usize).unwrap();
         }
 
+        // A new or reconfigured surface needs all of its content
+        self.renderer.invalidate();
         self.draw(conn, qh);
     }
 }
@@ -211,6 +806,10 @@ impl SeatHandler for WaylandApp {
         if _capability == Capability::Keyboard && self.seat_state.get_keyboard(qh, &seat, None).is_ok() {
             // Keyboard added
         }
+        if _capability == Capability::Pointer
+            && let Err(e) = self.seat_state.get_pointer(qh, &seat) {
+            log::warn!("Failed to get the pointer: {}", e);
+        }
     }
 
     fn remove_capability(
@@ -244,100 +843,213 @@ impl KeyboardHandler for WaylandApp {
         _: &wl_surface::WlSurface,
         _: u32,
     ) {
-        self.should_exit = true;
+        self.stop_key_repeat();
+        if self.state.config.general.close_on_focus_loss {
+            self.close();
+        }
     }
 
     fn press_key(
         &mut self,
-        _conn: &Connection,
+        conn: &Connection,
         qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
         _serial: u32,
         event: KeyEvent,
     ) {
+        self.stop_key_repeat();
+
+        // Only navigation and deletion repeat; a held Enter must not launch twice
+        if matches!(u32::from(event.keysym), keysyms::KEY_Up | keysyms::KEY_Down | keysyms::KEY_BackSpace) {
+            self.start_key_repeat(conn, qh, event.clone());
+        }
+        self.handle_key(conn, qh, event);
+    }
+
+    fn release_key(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _: u32,
+        event: KeyEvent,
+    ) {
+        if self.key_repeat.as_ref().is_some_and(|(code, _)| *code == event.raw_code) {
+            self.stop_key_repeat();
+        }
+    }
+
+    fn update_repeat_info(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        info: RepeatInfo,
+    ) {
+        self.repeat_info = info;
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _serial: u32,
+        modifiers: Modifiers,
+        _layout: u32,
+    ) {
+        self.modifiers = modifiers;
+    }
+}
+
+impl WaylandApp {
+    fn handle_key(&mut self, conn: &Connection, qh: &QueueHandle<Self>, event: KeyEvent) {
+         // Already closing
+         if self.fade.as_ref().is_some_and(|fade| fade.out) {
+             return;
+         }
          let sym = event.keysym;
          let raw_sym = u32::from(sym);
+         // A launch error stays up until the next key
+         self.state.error = None;
+         // Keep the cursor on while typing
+         if self.cursor_blink.is_some() {
+             self.restart_cursor_blink();
+         }
          
          match raw_sym {
-            keysyms::KEY_Escape => self.should_exit = true,
+            keysyms::KEY_Escape => {
+                // Back out of a script menu one level at a time, starting
+                // with the one still being listed
+                if self.script_pending.take().is_none() && !self.state.pop_script_level() {
+                    self.close();
+                }
+            }
             keysyms::KEY_Return => {
-                 if let Some(entry) = self.state.get_selected() {
-                     let _ = executor::execute(entry, &self.state.config, &self.state.active_group);
-                     self.should_exit = true;
+                 self.flush_filter();
+                 // Marks hidden by the query still count
+                 if !self.state.filtered_indices.is_empty() || !self.state.marked.is_empty() {
+                     self.launch_selected();
+                 } else if self.state.dmenu && !self.state.query.is_empty() {
+                     // Like dmenu, accept typed text that matches nothing
+                     self.dmenu_selection = vec![self.state.query.clone()];
+                     self.close();
                  }
             }
-            keysyms::KEY_Up => self.state.move_selection(-1),
-            keysyms::KEY_Down => self.state.move_selection(1),
+            keysyms::KEY_Up => {
+                self.flush_filter();
+                self.state.move_selection(-1);
+            }
+            keysyms::KEY_Down => {
+                self.flush_filter();
+                self.state.move_selection(1);
+            }
+            keysyms::KEY_Tab => {
+                self.flush_filter();
+                if self.state.multi {
+                    // Mark and move on, so a run of entries is quick to mark
+                    if self.state.toggle_mark() {
+                        self.state.move_selection(1);
+                    }
+                } else {
+                    self.state.complete(self.state.config.general.tab_completion);
+                }
+            }
             keysyms::KEY_BackSpace => {
+                // Deletes all of a selected query
+                self.state.edit_query();
                 self.state.query.pop();
-                self.state.update_query(&self.state.query.clone());
+                self.schedule_filter(conn, qh);
             }
-            keysyms::KEY_1 | keysyms::KEY_2 | keysyms::KEY_3 |
-            keysyms::KEY_4 | keysyms::KEY_5 | keysyms::KEY_6 |
-            keysyms::KEY_7 | keysyms::KEY_8 | keysyms::KEY_9 => {
-                let index_offset = (raw_sym - keysyms::KEY_1) as usize;
-                
-                let item_height = 30.0;
-                let list_start_y = self.state.config.theme.padding + 20.0 + self.state.config.theme.spacing;
-                let visible_items = (self.height as f32 - list_start_y - self.state.config.theme.padding) / item_height;
-                let visible_items = visible_items as usize;
-                
-                let total_items = self.state.filtered_indices.len();
-                let scroll_offset = if total_items <= visible_items {
-                    0
-                } else {
-                     if self.state.selected_index < visible_items / 2 {
-                         0
-                     } else if self.state.selected_index >= total_items - visible_items / 2 {
-                         total_items.saturating_sub(visible_items)
-                     } else {
-                         self.state.selected_index - visible_items / 2
-                     }
-                };
-
-                let target_index = scroll_offset + index_offset;
-                if let Some(&entry_idx) = self.state.filtered_indices.get(target_index) {
-                    let entry = &self.state.entries[entry_idx];
-                    let _ = executor::execute(entry, &self.state.config, &self.state.active_group);
-                    self.should_exit = true;
+            // Digits beyond quick_select_count, or without the required
+            // modifier, are typed into the query
+            keysyms::KEY_0..=keysyms::KEY_9
+                if quick_select_slot(raw_sym) < self.state.config.general.quick_select_slots()
+                    && self.state.config.general.quick_select_modifier.is_held(&self.modifiers) => {
+                self.flush_filter();
+                if let Some(target_index) = self.state.quick_select_target(quick_select_slot(raw_sym), self.height as f32) {
+                    self.state.selected_index = target_index;
+                    self.launch_selected();
                 }
             }
             _ => {
-                if let Some(utf8) = event.utf8 {
-                     if !utf8.chars().any(|c| c.is_control()) {
-                         self.state.query.push_str(&utf8);
-                         self.state.update_query(&self.state.query.clone());
-                     }
+                if let Some(utf8) = event.utf8
+                    && !utf8.chars().any(|c| c.is_control()) {
+                    self.state.edit_query();
+                    self.state.query.push_str(&utf8);
+                    self.schedule_filter(conn, qh);
                 }
             }
          }
          
-         if let Some(layer_surface) = &self.layer_surface {
-             layer_surface.wl_surface().frame(qh, layer_surface.wl_surface().clone());
-             layer_surface.wl_surface().commit();
+         if let Some(surface) = &self.surface {
+             surface.wl_surface().frame(qh, surface.wl_surface().clone());
+             surface.commit();
          }
     }
+}
 
-    fn release_key(
-        &mut self,
-        _: &Connection,
-        _: &QueueHandle<Self>,
-        _: &wl_keyboard::WlKeyboard,
-        _: u32,
-        _: KeyEvent,
-    ) {}
+/// Row a digit key selects: 1-9 for the first nine, 0 for the tenth
+fn quick_select_slot(raw_sym: u32) -> usize {
+    if raw_sym == keysyms::KEY_0 { 9 } else { (raw_sym - keysyms::KEY_1) as usize }
+}
 
-    fn update_modifiers(
+/// Linux input code of the left mouse button
+const BTN_LEFT: u32 = 0x110;
+
+impl PointerHandler for WaylandApp {
+    /// Hovering highlights the row under the pointer, or selects it with
+    /// `hover_selects`; a left click launches it.
+    fn pointer_frame(
         &mut self,
-        _: &Connection,
-        _: &QueueHandle<Self>,
-        _: &wl_keyboard::WlKeyboard,
-        _serial: u32,
-        _modifiers: Modifiers,
-        _layout: u32,
-    ) {}
-}
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        _: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        let Some(surface) = &self.surface else { return };
+        let surface = surface.wl_surface().clone();
+        if self.fade.as_ref().is_some_and(|fade| fade.out) {
+            return;
+        }
 
+        let mut changed = false;
+        for event in events.iter().filter(|event| event.surface == surface) {
+            let under = self.state.entry_at(event.position.1 as f32, self.height as f32);
+            match event.kind {
+                PointerEventKind::Enter { .. } | PointerEventKind::Motion { .. } => {
+                    if self.state.config.general.hover_selects {
+                        if let Some(position) = under
+                            && position != self.state.selected_index {
+                            self.state.selected_index = position;
+                            changed = true;
+                        }
+                    } else if under != self.state.hover_index {
+                        self.state.hover_index = under;
+                        changed = true;
+                    }
+                }
+                PointerEventKind::Leave { .. } => {
+                    changed |= self.state.hover_index.take().is_some();
+                }
+                PointerEventKind::Press { button: BTN_LEFT, .. } => {
+                    self.flush_filter();
+                    // The list may have changed under the pointer
+                    if let Some(position) = self.state.entry_at(event.position.1 as f32, self.height as f32) {
+                        self.state.error = None;
+                        self.state.selected_index = position;
+                        self.launch_selected();
+                        changed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if changed && self.surface.is_some() {
+            self.draw(conn, qh);
+        }
+    }
+}
 
 impl ShmHandler for WaylandApp {
     fn shm_state(&mut self) -> &mut Shm {
@@ -350,7 +1062,10 @@ delegate_output!(WaylandApp);
 delegate_shm!(WaylandApp);
 delegate_seat!(WaylandApp);
 delegate_keyboard!(WaylandApp);
+delegate_pointer!(WaylandApp);
 delegate_layer!(WaylandApp);
+delegate_xdg_shell!(WaylandApp);
+delegate_xdg_window!(WaylandApp);
 delegate_registry!(WaylandApp);
 
 impl ProvidesRegistryState for WaylandApp {
@@ -363,3 +1078,18 @@ impl ProvidesRegistryState for WaylandApp {
     fn runtime_remove_global(&mut self, _: &Connection, _: &QueueHandle<Self>, _: u32, _: &str) {
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argb8888_is_a_native_endian_word_per_pixel() {
+        let mut canvas = [0x11, 0x22, 0x33, 0x80, 0xff, 0x00, 0x00, 0xff];
+        to_argb8888(&mut canvas);
+        let words: Vec<u32> = canvas.chunks_exact(4)
+            .map(|p| u32::from_ne_bytes([p[0], p[1], p[2], p[3]]))
+            .collect();
+        assert_eq!(words, [0x80112233, 0xffff0000]);
+    }
+}